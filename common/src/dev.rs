@@ -0,0 +1,28 @@
+// Device files are exposed under this directory
+pub const DEV_PATH: &str = "/dev/";
+
+// Console and VGA ioctl requests
+pub const IOCTL_SET_CURSOR: u32     = 0x01;
+pub const IOCTL_GET_CURSOR: u32     = 0x02;
+pub const IOCTL_CURSOR_DISABLE: u32 = 0x03;
+pub const IOCTL_SET_COLOR: u32      = 0x04;
+pub const IOCTL_CLEAR: u32          = 0x05;
+
+// Keyboard ioctl requests
+pub const IOCTL_KEYPRESSED: u32     = 0x10;
+
+// Disk ioctl requests
+pub const IOCTL_SECTOR_SIZE: u32    = 0x20;
+
+// Random generator ioctl requests
+pub const IOCTL_SEED: u32           = 0x30;
+
+/// Packs a cursor position into a single ioctl argument
+pub const fn cursor_to_arg(x: u32, y: u32) -> u32 {
+    (y << 16) | (x & 0xffff)
+}
+
+/// Unpacks a cursor position from an ioctl argument or return value
+pub const fn arg_to_cursor(arg: u32) -> (u32, u32) {
+    (arg & 0xffff, arg >> 16)
+}
\ No newline at end of file
@@ -0,0 +1,88 @@
+use core::fmt;
+
+/// Values returned by the system calls from -MAX_ERROR to -1 are errors
+pub const MAX_ERROR: i32 = 4095;
+
+/// Errors of the system calls, returned as the negated code. The codes are
+/// the ones of errno.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    NotFound        = 2,    // ENOENT
+    NotExecutable   = 8,    // ENOEXEC
+    BadFd           = 9,    // EBADF
+    NoTask          = 11,   // EAGAIN
+    NoMemory        = 12,   // ENOMEM
+    Fault           = 14,   // EFAULT
+    Invalid         = 22,   // EINVAL
+    TooManyFiles    = 24,   // EMFILE
+    NotDevice       = 25,   // ENOTTY
+    NoSpace         = 28,   // ENOSPC
+    ReadOnly        = 30,   // EROFS
+    NameTooLong     = 36    // ENAMETOOLONG
+}
+
+impl Error {
+    /// Value returned by the system call
+    pub fn code(self) -> i32 {
+        -(self as i32)
+    }
+    
+    pub fn from_code(code: i32) -> Option<Error> {
+        match -code {
+            2 => Some(Error::NotFound),
+            8 => Some(Error::NotExecutable),
+            9 => Some(Error::BadFd),
+            11 => Some(Error::NoTask),
+            12 => Some(Error::NoMemory),
+            14 => Some(Error::Fault),
+            22 => Some(Error::Invalid),
+            24 => Some(Error::TooManyFiles),
+            25 => Some(Error::NotDevice),
+            28 => Some(Error::NoSpace),
+            30 => Some(Error::ReadOnly),
+            36 => Some(Error::NameTooLong),
+            _ => None
+        }
+    }
+    
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Error::NotFound => "No such file or directory",
+            Error::NotExecutable => "Exec format error",
+            Error::BadFd => "Bad file descriptor",
+            Error::NoTask => "No free task slot",
+            Error::NoMemory => "Out of memory",
+            Error::Fault => "Bad address",
+            Error::Invalid => "Invalid argument",
+            Error::TooManyFiles => "Too many open files",
+            Error::NotDevice => "Not a device",
+            Error::NoSpace => "No space left on device",
+            Error::ReadOnly => "Read-only file",
+            Error::NameTooLong => "File name too long"
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Value returned by a system call for the result
+pub fn result_to_code(result: Result<u32, Error>) -> i32 {
+    match result {
+        Ok(value) => value as i32,
+        Err(err) => err.code()
+    }
+}
+
+/// Result of a system call from the value it returned
+pub fn code_to_result(code: i32) -> Result<u32, Error> {
+    if code < 0 && code >= -MAX_ERROR {
+        Err(Error::from_code(code).unwrap_or(Error::Invalid))
+    } else {
+        Ok(code as u32)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,36 @@
+use mman::{USER_CODE_START, USER_FRAMES_START};
+
+/// Magic number of the programs. Its first byte is not ASCII so that they are
+/// never taken for text files.
+pub const EXEC_MAGIC: u32 = 0x455845ff;
+
+/// Header at the start of the programs, written by the linker script of the
+/// apps (user/app.ld). Addresses are the ones of the user address space, the
+/// program being loaded at USER_CODE_START.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ExecHeader {
+    pub magic: u32,
+    pub entry: u32,     // address of the first instruction
+    pub text_end: u32,  // end of the code and read-only data, page aligned
+    pub end: u32        // end of the data and bss
+}
+
+impl ExecHeader {
+    pub const fn null() -> ExecHeader {
+        ExecHeader {
+            magic: 0,
+            entry: 0,
+            text_end: 0,
+            end: 0
+        }
+    }
+    
+    /// Checks the header read from a program of size bytes
+    pub fn is_valid(&self, size: usize) -> bool {
+        self.magic == EXEC_MAGIC && self.text_end % 0x1000 == 0 &&
+            USER_CODE_START < self.entry && self.entry < self.text_end &&
+            self.text_end <= self.end && self.end <= USER_FRAMES_START &&
+            size <= (self.end - USER_CODE_START) as usize
+    }
+}
\ No newline at end of file
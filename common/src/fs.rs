@@ -1,12 +1,57 @@
 pub const MAX_FILENAME_LENGTH: usize = 26;
 
+// Layout of a MicroFS directory entry
+pub const ENTRY_SIZE: usize = 32;
+pub const ENTRY_START_OFFSET: usize = 26;   // u16, first block of the file
+pub const ENTRY_SIZE_OFFSET: usize = 28;    // u32, size in bytes
+
+// Layout of a record of the metadata table, which holds one record per
+// directory entry, at the same index
+pub const META_RECORD_SIZE: usize = 16;
+pub const META_FLAGS_OFFSET: usize = 0;     // u8, META_USED once the record is filled
+pub const META_KIND_OFFSET: usize = 1;      // u8, see FileKind
+pub const META_MODE_OFFSET: usize = 2;      // u16, permission bits
+pub const META_UID_OFFSET: usize = 4;       // u16, owner id
+pub const META_CTIME_OFFSET: usize = 8;     // u32, creation time
+pub const META_MTIME_OFFSET: usize = 12;    // u32, modification time
+pub const META_USED: u8 = 0x1;
+
+// Permission bits
+pub const MODE_USER_READ: u16   = 0o400;
+pub const MODE_USER_WRITE: u16  = 0o200;
+pub const MODE_USER_EXEC: u16   = 0o100;
+pub const MODE_GROUP_READ: u16  = 0o040;
+pub const MODE_GROUP_WRITE: u16 = 0o020;
+pub const MODE_GROUP_EXEC: u16  = 0o010;
+pub const MODE_OTHER_READ: u16  = 0o004;
+pub const MODE_OTHER_WRITE: u16 = 0o002;
+pub const MODE_OTHER_EXEC: u16  = 0o001;
+
+pub const ROOT_UID: u16 = 0;
+// permissions of the files that have no metadata record
+pub const DEFAULT_MODE: u16 = 0o644;
+pub const DEFAULT_EXEC_MODE: u16 = 0o755;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    File    = 0x0,
+    Dir     = 0x1,
+    Device  = 0x2
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct Stat {
     pub name: [u8;MAX_FILENAME_LENGTH],
     pub size: usize,
-    pub entry_offset: u16,
-    pub start: usize
+    pub entry_offset: u16,  // position of the entry in the root directory
+    pub start: usize,
+    pub kind: FileKind,
+    pub mode: u16,
+    pub uid: u16,
+    pub ctime: u32,
+    pub mtime: u32
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -16,19 +61,51 @@ pub struct FileIterator {
     pub offset: usize
 }
 
+impl FileKind {
+    pub fn from_u8(kind: u8) -> FileKind {
+        match kind {
+            0x1 => FileKind::Dir,
+            0x2 => FileKind::Device,
+            _ => FileKind::File
+        }
+    }
+}
+
 impl Stat {
-    pub fn null() -> Stat {
+    pub const fn null() -> Stat {
         Stat {
             name: [0;MAX_FILENAME_LENGTH],
             size: 0,
             entry_offset: 0,
-            start: 0
+            start: 0,
+            kind: FileKind::File,
+            mode: 0,
+            uid: 0,
+            ctime: 0,
+            mtime: 0
         }
     }
     
     pub fn as_ptr(&mut self) -> *const Stat {
         self as *const Stat
     }
+    
+    /// Returns the kind and permissions in the ls format, e.g. "-rwxr-xr-x"
+    pub fn mode_str(&self) -> [u8;10] {
+        let mut s = [b'-';10];
+        s[0] = match self.kind {
+            FileKind::File => b'-',
+            FileKind::Dir => b'd',
+            FileKind::Device => b'c'
+        };
+        let flags = b"rwxrwxrwx";
+        for i in 0..9 {
+            if self.mode & (1 << (8 - i)) != 0 {
+                s[i+1] = flags[i];
+            }
+        }
+        return s;
+    }
 }
 
 impl FileIterator {
@@ -0,0 +1,348 @@
+//! MicroFS consistency checker shared by the kernel and the host tools.
+//! The FAT holds one byte per block: the index of the next block of the file,
+//! FAT_FREE for unused blocks and FAT_END for the last block of a file.
+
+use fs::*;
+use journal::JOURNAL_MIN_SECTORS;
+
+pub const SECTOR_SIZE: usize = 512;
+pub const FAT_START_SECTOR: u32 = 1;
+pub const FAT_FREE: u8 = 0x00;
+pub const FAT_END: u8 = 0xff;
+pub const MAX_BLOCKS: usize = 0x100;
+
+// Superblock fields
+const SB_SECTORS_PER_BLOCK_OFFSET: usize = 0x0d;
+const SB_FAT_SIZE_OFFSET: usize = 0x24;
+const SB_ROOT_ENTRY_OFFSET: usize = 0x2c;
+pub const SB_JOURNAL_START_OFFSET: usize = 0x60;      // u32, first sector of the journal
+pub const SB_JOURNAL_SECTORS_OFFSET: usize = 0x64;    // u32, 0 if the image has no journal
+pub const SB_META_START_OFFSET: usize = 0x68;         // u32, first sector of the metadata table
+pub const SB_META_SECTORS_OFFSET: usize = 0x6c;       // u32, 0 if the image has no metadata table
+
+const NO_OWNER: u16 = 0xffff;
+
+/// Sector based access to the disk holding the file system
+pub trait BlockDevice {
+    fn read_sector(&mut self, sector: u32, buf: &mut [u8;SECTOR_SIZE]);
+    fn write_sector(&mut self, sector: u32, buf: &[u8;SECTOR_SIZE]);
+}
+
+/// Layout of a MicroFS image as described by its superblock
+#[derive(Debug, Clone, Copy)]
+pub struct FsGeometry {
+    pub block_size: usize,
+    pub fat_size: usize,
+    pub root_entry: usize,
+    pub journal_start: u32,
+    pub journal_sectors: u32,
+    pub meta_start: u32,
+    pub meta_sectors: u32
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FsckReport {
+    pub files: usize,
+    pub used_blocks: usize,
+    pub lost_blocks: usize,
+    pub cross_links: usize,
+    pub bad_chains: usize,
+    pub size_mismatches: usize,
+    pub repaired: bool
+}
+
+impl FsGeometry {
+    /// Reads the layout of the image from its superblock, None if it cannot
+    /// describe a MicroFS image
+    pub fn from_superblock(raw_sb: &[u8]) -> Option<FsGeometry> {
+        if raw_sb[SB_SECTORS_PER_BLOCK_OFFSET] == 0 {
+            return None;
+        }
+        let mut geometry = FsGeometry {
+            block_size: raw_sb[SB_SECTORS_PER_BLOCK_OFFSET] as usize * SECTOR_SIZE,
+            fat_size: get_u32(raw_sb, SB_FAT_SIZE_OFFSET) as usize,
+            root_entry: raw_sb[SB_ROOT_ENTRY_OFFSET] as usize,
+            journal_start: get_u32(raw_sb, SB_JOURNAL_START_OFFSET),
+            journal_sectors: get_u32(raw_sb, SB_JOURNAL_SECTORS_OFFSET),
+            meta_start: get_u32(raw_sb, SB_META_START_OFFSET),
+            meta_sectors: get_u32(raw_sb, SB_META_SECTORS_OFFSET)
+        };
+        // images created without a journal may hold anything in these bytes
+        let first_free_sector = ((geometry.root_entry + 1) * geometry.sectors_per_block()) as u32;
+        let last_sector = (geometry.blocks() * geometry.sectors_per_block()) as u32;
+        if geometry.journal_sectors < JOURNAL_MIN_SECTORS || geometry.journal_start < first_free_sector ||
+            geometry.journal_start.checked_add(geometry.journal_sectors).map_or(true, |end| end > last_sector) {
+            geometry.journal_start = 0;
+            geometry.journal_sectors = 0;
+        }
+        if geometry.meta_sectors < geometry.meta_sectors_needed() || geometry.meta_start < first_free_sector ||
+            geometry.meta_start.checked_add(geometry.meta_sectors).map_or(true, |end| end > last_sector) {
+            geometry.meta_start = 0;
+            geometry.meta_sectors = 0;
+        }
+        return Some(geometry);
+    }
+    
+    pub fn has_journal(&self) -> bool {
+        self.journal_sectors != 0
+    }
+
+    pub fn has_meta(&self) -> bool {
+        self.meta_sectors != 0
+    }
+
+    /// Number of entries of the root directory
+    pub fn entries(&self) -> usize {
+        self.block_size / ENTRY_SIZE
+    }
+
+    /// Number of sectors of a metadata table holding a record per root entry
+    pub fn meta_sectors_needed(&self) -> u32 {
+        ((self.entries() * META_RECORD_SIZE + SECTOR_SIZE - 1) / SECTOR_SIZE) as u32
+    }
+
+    /// Number of sectors of a journal holding a transaction that updates
+    /// the superblock, the FAT, the root directory and the metadata table
+    pub fn journal_sectors_needed(&self) -> u32 {
+        let fat_sectors = (self.blocks() + SECTOR_SIZE - 1) / SECTOR_SIZE;
+        // the header comes first
+        (1 + 1 + fat_sectors + self.sectors_per_block()) as u32 + self.meta_sectors_needed()
+    }
+
+    /// Sectors holding the superblock, the FAT, the root directory or the
+    /// metadata table, whose updates must go through a Journal
+    pub fn is_metadata(&self, sector: u32) -> bool {
+        (sector as usize) < (self.root_entry + 1) * self.sectors_per_block() ||
+            (self.has_meta() && self.meta_start <= sector && sector < self.meta_start + self.meta_sectors)
+    }
+
+    pub fn sectors_per_block(&self) -> usize {
+        self.block_size / SECTOR_SIZE
+    }
+
+    /// Number of blocks described by the FAT
+    pub fn blocks(&self) -> usize {
+        if self.fat_size < MAX_BLOCKS { self.fat_size } else { MAX_BLOCKS }
+    }
+
+    /// Number of blocks needed to store size bytes
+    pub fn blocks_for(&self, size: usize) -> usize {
+        (size + self.block_size - 1) / self.block_size
+    }
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.lost_blocks == 0 && self.cross_links == 0 && self.bad_chains == 0 && self.size_mismatches == 0
+    }
+}
+
+/// Walks the FAT chain of every file of the root directory and reports lost
+/// blocks, blocks shared by several files, broken chains and sizes that do not
+/// match the chain length. Problems are fixed on the disk if repair is set:
+/// chains are cut at the first bad block, sizes are clamped to the chain
+/// and lost blocks are freed. A file whose first block is bad is left empty,
+/// which a later check finds consistent.
+pub fn fsck<D: BlockDevice>(dev: &mut D, geometry: &FsGeometry, repair: bool) -> FsckReport {
+    let mut report = FsckReport {
+        files: 0, used_blocks: 0, lost_blocks: 0, cross_links: 0,
+        bad_chains: 0, size_mismatches: 0, repaired: false
+    };
+    let blocks = geometry.blocks();
+    let mut fat = [FAT_FREE;MAX_BLOCKS];
+    let mut owner = [NO_OWNER;MAX_BLOCKS];
+    let mut fat_dirty = false;
+    read_fat(dev, &mut fat, blocks);
+
+    // blocks up to the root directory hold the superblock, the FAT and the root entries
+    for block in 0..(geometry.root_entry + 1) {
+        owner[block] = 0;
+    }
+    // as well as the blocks of the journal and of the metadata table
+    if geometry.has_journal() {
+        own_sectors(&mut owner, geometry, geometry.journal_start, geometry.journal_sectors);
+    }
+    if geometry.has_meta() {
+        own_sectors(&mut owner, geometry, geometry.meta_start, geometry.meta_sectors);
+    }
+
+    let mut sector = [0;SECTOR_SIZE];
+    let root_sector = (geometry.root_entry * geometry.sectors_per_block()) as u32;
+    let mut entry_idx: u16 = 1;
+    'sectors: for sector_id in root_sector..(root_sector + geometry.sectors_per_block() as u32) {
+        dev.read_sector(sector_id, &mut sector);
+        let mut sector_dirty = false;
+        for offset in (0..SECTOR_SIZE).step_by(ENTRY_SIZE) {
+            if sector[offset] == 0 {
+                if sector_dirty {
+                    dev.write_sector(sector_id, &sector);
+                }
+                break 'sectors;
+            }
+            report.files += 1;
+            let entry = &mut sector[offset..offset+ENTRY_SIZE];
+            let size = get_u32(entry, ENTRY_SIZE_OFFSET) as usize;
+            let start = get_u16(entry, ENTRY_START_OFFSET) as usize;
+            // a file owns at least its first block, unless a repair left it empty
+            let expected = if size == 0 && start == FAT_END as usize {
+                0
+            } else if size == 0 {
+                1
+            } else {
+                geometry.blocks_for(size)
+            };
+            let mut block = start;
+            let mut previous = MAX_BLOCKS;
+            let mut cnt = 0;
+
+            while block != FAT_END as usize {
+                if block == 0 || block >= blocks || fat[block] == FAT_FREE {
+                    report.bad_chains += 1;
+                    break;
+                }
+                if owner[block] != NO_OWNER {
+                    report.cross_links += 1;
+                    break;
+                }
+                owner[block] = entry_idx;
+                cnt += 1;
+                previous = block;
+                block = fat[block] as usize;
+            }
+            report.used_blocks += cnt;
+
+            if block != FAT_END as usize && repair {
+                // cut the chain before the bad block
+                if previous == MAX_BLOCKS {
+                    set_u16(entry, ENTRY_START_OFFSET, FAT_END as u16);
+                } else {
+                    fat[previous] = FAT_END;
+                    fat_dirty = true;
+                }
+                sector_dirty = true;
+            }
+            if cnt != expected {
+                report.size_mismatches += 1;
+                if repair && cnt > expected {
+                    // release the blocks past the end of the file, they are freed as lost blocks below
+                    let mut last = start;
+                    for _ in 1..expected {
+                        last = fat[last] as usize;
+                    }
+                    let mut extra = fat[last] as usize;
+                    fat[last] = FAT_END;
+                    fat_dirty = true;
+                    while extra != FAT_END as usize && owner[extra] == entry_idx {
+                        owner[extra] = NO_OWNER;
+                        extra = fat[extra] as usize;
+                    }
+                    report.used_blocks -= cnt - expected;
+                } else if repair {
+                    set_u32(entry, ENTRY_SIZE_OFFSET, (cnt * geometry.block_size) as u32);
+                    sector_dirty = true;
+                }
+            }
+            entry_idx += 1;
+        }
+        if sector_dirty {
+            dev.write_sector(sector_id, &sector);
+        }
+    }
+
+    for block in 0..blocks {
+        if fat[block] != FAT_FREE && owner[block] == NO_OWNER {
+            report.lost_blocks += 1;
+            if repair {
+                fat[block] = FAT_FREE;
+                fat_dirty = true;
+            }
+        }
+    }
+
+    if repair && (fat_dirty || !report.is_clean()) {
+        if fat_dirty {
+            write_fat(dev, &fat, blocks);
+        }
+        report.repaired = true;
+    }
+    return report;
+}
+
+/// Marks the last run of blocks_nb free blocks of the disk as used and
+/// returns the first one, None if there is no such run.
+pub(crate) fn reserve_blocks<D: BlockDevice>(dev: &mut D, geometry: &FsGeometry, blocks_nb: usize) -> Option<usize> {
+    let blocks = geometry.blocks();
+    let mut fat = [FAT_FREE;MAX_BLOCKS];
+    read_fat(dev, &mut fat, blocks);
+    if blocks_nb == 0 {
+        return None;
+    }
+    let mut end = blocks;
+    while end >= geometry.root_entry + 1 + blocks_nb {
+        let first = end - blocks_nb;
+        if fat[first..end].iter().all(|&next| next == FAT_FREE) {
+            for block in first..end {
+                fat[block] = FAT_END;
+            }
+            write_fat(dev, &fat, blocks);
+            return Some(first);
+        }
+        end -= 1;
+    }
+    return None;
+}
+
+// Blocks holding the given sectors belong to the file system itself
+fn own_sectors(owner: &mut [u16;MAX_BLOCKS], geometry: &FsGeometry, start: u32, sectors: u32) {
+    let first = start as usize / geometry.sectors_per_block();
+    let last = (start + sectors - 1) as usize / geometry.sectors_per_block();
+    for block in first..(last + 1) {
+        owner[block] = 0;
+    }
+}
+
+pub(crate) fn read_fat<D: BlockDevice>(dev: &mut D, fat: &mut [u8;MAX_BLOCKS], blocks: usize) {
+    let mut sector = [0;SECTOR_SIZE];
+    for i in 0..(blocks + SECTOR_SIZE - 1) / SECTOR_SIZE {
+        dev.read_sector(FAT_START_SECTOR + i as u32, &mut sector);
+        for j in 0..SECTOR_SIZE {
+            if i * SECTOR_SIZE + j < blocks {
+                fat[i * SECTOR_SIZE + j] = sector[j];
+            }
+        }
+    }
+}
+
+pub(crate) fn write_fat<D: BlockDevice>(dev: &mut D, fat: &[u8;MAX_BLOCKS], blocks: usize) {
+    let mut sector = [0;SECTOR_SIZE];
+    for i in 0..(blocks + SECTOR_SIZE - 1) / SECTOR_SIZE {
+        // keep the bytes of the sector that are not part of the FAT
+        dev.read_sector(FAT_START_SECTOR + i as u32, &mut sector);
+        for j in 0..SECTOR_SIZE {
+            if i * SECTOR_SIZE + j < blocks {
+                sector[j] = fat[i * SECTOR_SIZE + j];
+            }
+        }
+        dev.write_sector(FAT_START_SECTOR + i as u32, &sector);
+    }
+}
+
+pub(crate) fn get_u16(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | (bytes[offset+1] as u16) << 8
+}
+
+pub(crate) fn get_u32(bytes: &[u8], offset: usize) -> u32 {
+    bytes[offset] as u32 | (bytes[offset+1] as u32) << 8 |
+        (bytes[offset+2] as u32) << 16 | (bytes[offset+3] as u32) << 24
+}
+
+pub(crate) fn set_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset] = value as u8;
+    bytes[offset+1] = (value >> 8) as u8;
+}
+
+pub(crate) fn set_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        bytes[offset+i] = (value >> (8 * i)) as u8;
+    }
+}
\ No newline at end of file
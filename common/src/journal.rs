@@ -0,0 +1,173 @@
+//! Write-ahead journal for the metadata of MicroFS.
+//! The journal is a region of contiguous sectors recorded in the superblock.
+//! Its first sector is the header: a magic number, the number of logged
+//! sectors and their home location. The following sectors hold the new
+//! content of the logged sectors, in the same order.
+//! A transaction is written to the journal first, then committed by writing
+//! the header in a single sector write, and finally copied to its home
+//! location. The header is cleared once the copy is done, so a transaction
+//! interrupted before the commit is lost and one interrupted after is
+//! replayed at the next mount.
+//! A transaction must fit in the journal: one that does not is dropped as a
+//! whole when it is committed, so its sectors never reach the disk.
+
+use fsck::*;
+use error::Error;
+
+pub const JOURNAL_MAGIC: u32 = 0x4a53464d;    // "MFSJ"
+pub const JOURNAL_MIN_SECTORS: u32 = 2;
+pub const JOURNAL_MAX_ENTRIES: usize = (SECTOR_SIZE - JOURNAL_TARGETS_OFFSET) / 4;
+
+// Header fields
+const JOURNAL_MAGIC_OFFSET: usize = 0;
+const JOURNAL_COUNT_OFFSET: usize = 4;
+const JOURNAL_TARGETS_OFFSET: usize = 8;
+
+/// Transaction on a block device. Sectors written through the journal only
+/// reach their home location when the transaction is committed.
+/// Without a journal region, writes go directly to the device.
+pub struct Journal<'a, D: BlockDevice + 'a> {
+    dev: &'a mut D,
+    start: u32,
+    capacity: usize,
+    targets: [u32;JOURNAL_MAX_ENTRIES],
+    count: usize,
+    overflow: bool
+}
+
+impl<'a, D: BlockDevice> Journal<'a, D> {
+    pub fn begin(dev: &'a mut D, geometry: &FsGeometry) -> Journal<'a, D> {
+        let capacity = if geometry.has_journal() {
+            let data_sectors = geometry.journal_sectors as usize - 1;
+            if data_sectors < JOURNAL_MAX_ENTRIES { data_sectors } else { JOURNAL_MAX_ENTRIES }
+        } else {
+            0
+        };
+        Journal {
+            dev: dev,
+            start: geometry.journal_start,
+            capacity: capacity,
+            targets: [0;JOURNAL_MAX_ENTRIES],
+            count: 0,
+            overflow: false
+        }
+    }
+    
+    /// Makes the logged sectors durable and copies them to their home location.
+    /// The journal can be used for a new transaction afterwards.
+    /// Returns NoSpace, and leaves the disk untouched, if the transaction
+    /// wrote more sectors than the journal holds.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        if self.overflow {
+            self.count = 0;
+            self.overflow = false;
+            return Err(Error::NoSpace);
+        }
+        if self.count == 0 {
+            return Ok(());
+        }
+        let mut header = [0;SECTOR_SIZE];
+        set_u32(&mut header, JOURNAL_MAGIC_OFFSET, JOURNAL_MAGIC);
+        set_u32(&mut header, JOURNAL_COUNT_OFFSET, self.count as u32);
+        for i in 0..self.count {
+            set_u32(&mut header, JOURNAL_TARGETS_OFFSET + i * 4, self.targets[i]);
+        }
+        self.dev.write_sector(self.start, &header);
+        checkpoint(self.dev, self.start, &self.targets[0..self.count]);
+        self.count = 0;
+        return Ok(());
+    }
+    
+    /// Number of different sectors a transaction can write, 0 if the writes
+    /// go directly to the device
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    
+    fn slot(&self, sector: u32) -> Option<usize> {
+        self.targets[0..self.count].iter().position(|&target| target == sector)
+    }
+}
+
+impl<'a, D: BlockDevice> BlockDevice for Journal<'a, D> {
+    fn read_sector(&mut self, sector: u32, buf: &mut [u8;SECTOR_SIZE]) {
+        match self.slot(sector) {
+            Some(i) => self.dev.read_sector(self.start + 1 + i as u32, buf),
+            None => self.dev.read_sector(sector, buf)
+        }
+    }
+    
+    fn write_sector(&mut self, sector: u32, buf: &[u8;SECTOR_SIZE]) {
+        if self.capacity == 0 {
+            self.dev.write_sector(sector, buf);
+            return;
+        }
+        let i = match self.slot(sector) {
+            Some(i) => i,
+            None => {
+                // the transaction is dropped at the commit
+                if self.count == self.capacity {
+                    self.overflow = true;
+                    return;
+                }
+                self.targets[self.count] = sector;
+                self.count += 1;
+                self.count - 1
+            }
+        };
+        self.dev.write_sector(self.start + 1 + i as u32, buf);
+    }
+}
+
+/// Copies the transaction committed in the journal, if any, to its home
+/// location. Returns the number of sectors replayed.
+pub fn journal_replay<D: BlockDevice>(dev: &mut D, geometry: &FsGeometry) -> usize {
+    if !geometry.has_journal() {
+        return 0;
+    }
+    let mut header = [0;SECTOR_SIZE];
+    dev.read_sector(geometry.journal_start, &mut header);
+    let count = get_u32(&header, JOURNAL_COUNT_OFFSET) as usize;
+    if get_u32(&header, JOURNAL_MAGIC_OFFSET) != JOURNAL_MAGIC || count == 0 ||
+        count > JOURNAL_MAX_ENTRIES || count >= geometry.journal_sectors as usize {
+        return 0;
+    }
+    let mut targets = [0;JOURNAL_MAX_ENTRIES];
+    for i in 0..count {
+        targets[i] = get_u32(&header, JOURNAL_TARGETS_OFFSET + i * 4);
+    }
+    checkpoint(dev, geometry.journal_start, &targets[0..count]);
+    return count;
+}
+
+/// Reserves blocks at the end of the disk for the journal and records it
+/// in the superblock. Returns false if there are not enough free blocks or
+/// if the journal would be too small to hold an update of all the metadata.
+pub fn journal_create<D: BlockDevice>(dev: &mut D, geometry: &FsGeometry, blocks_nb: usize) -> bool {
+    if ((blocks_nb * geometry.sectors_per_block()) as u32) < geometry.journal_sectors_needed() {
+        return false;
+    }
+    let first = match reserve_blocks(dev, geometry, blocks_nb) {
+        Some(first) => first,
+        None => return false
+    };
+    let start = (first * geometry.sectors_per_block()) as u32;
+    let mut sector = [0;SECTOR_SIZE];
+    dev.write_sector(start, &sector);
+    dev.read_sector(0, &mut sector);
+    set_u32(&mut sector, SB_JOURNAL_START_OFFSET, start);
+    set_u32(&mut sector, SB_JOURNAL_SECTORS_OFFSET, (blocks_nb * geometry.sectors_per_block()) as u32);
+    dev.write_sector(0, &sector);
+    return true;
+}
+
+// Copies the logged sectors to their home location and clears the header
+fn checkpoint<D: BlockDevice>(dev: &mut D, start: u32, targets: &[u32]) {
+    let mut sector = [0;SECTOR_SIZE];
+    for (i, &target) in targets.iter().enumerate() {
+        dev.read_sector(start + 1 + i as u32, &mut sector);
+        dev.write_sector(target, &sector);
+    }
+    let header = [0;SECTOR_SIZE];
+    dev.write_sector(start, &header);
+}
\ No newline at end of file
@@ -6,9 +6,27 @@ mod string;
 mod fs;
 mod keyboard;
 mod vga;
+mod dev;
+mod proc;
+mod time;
+mod fsck;
+mod journal;
+mod meta;
+mod mman;
+mod error;
+mod exec;
 
 pub use syscall::*;
 pub use string::*;
 pub use fs::*;
 pub use keyboard::*;
-pub use vga::*;
\ No newline at end of file
+pub use vga::*;
+pub use dev::*;
+pub use proc::*;
+pub use time::*;
+pub use fsck::*;
+pub use journal::*;
+pub use meta::*;
+pub use mman::*;
+pub use error::*;
+pub use exec::*;
\ No newline at end of file
@@ -0,0 +1,122 @@
+//! Metadata table of MicroFS: kind, permissions, owner and times of the files.
+//! The directory entries of MicroFS only hold a name, a first block and a size,
+//! so these fields live in a region of contiguous sectors recorded in the
+//! superblock. It holds one record per entry of the root directory, at the
+//! same index. Files without a record get default values.
+
+use fs::*;
+use fsck::*;
+use exec::EXEC_MAGIC;
+
+/// Reserves blocks at the end of the disk for an empty metadata table and
+/// records it in the superblock. Returns false if there are not enough free blocks.
+pub fn meta_create<D: BlockDevice>(dev: &mut D, geometry: &FsGeometry) -> bool {
+    let sectors = geometry.meta_sectors_needed();
+    let blocks_nb = geometry.blocks_for(sectors as usize * SECTOR_SIZE);
+    let first = match reserve_blocks(dev, geometry, blocks_nb) {
+        Some(first) => first,
+        None => return false
+    };
+    let start = (first * geometry.sectors_per_block()) as u32;
+    let mut sector = [0;SECTOR_SIZE];
+    for i in 0..sectors {
+        dev.write_sector(start + i, &sector);
+    }
+    dev.read_sector(0, &mut sector);
+    set_u32(&mut sector, SB_META_START_OFFSET, start);
+    set_u32(&mut sector, SB_META_SECTORS_OFFSET, sectors);
+    dev.write_sector(0, &sector);
+    return true;
+}
+
+/// Fills the kind, mode, owner and times of stat from the record of its
+/// entry, or with default values if it has none. Returns false in that case.
+pub fn meta_read<D: BlockDevice>(dev: &mut D, geometry: &FsGeometry, stat: &mut Stat) -> bool {
+    stat.kind = FileKind::File;
+    stat.mode = DEFAULT_MODE;
+    stat.uid = ROOT_UID;
+    stat.ctime = 0;
+    stat.mtime = 0;
+    if !geometry.has_meta() {
+        return false;
+    }
+    let (sector_id, offset) = record_location(geometry, stat.entry_offset as usize);
+    let mut sector = [0;SECTOR_SIZE];
+    dev.read_sector(sector_id, &mut sector);
+    let record = &sector[offset..offset+META_RECORD_SIZE];
+    if record[META_FLAGS_OFFSET] & META_USED == 0 {
+        return false;
+    }
+    stat.kind = FileKind::from_u8(record[META_KIND_OFFSET]);
+    stat.mode = get_u16(record, META_MODE_OFFSET);
+    stat.uid = get_u16(record, META_UID_OFFSET);
+    stat.ctime = get_u32(record, META_CTIME_OFFSET);
+    stat.mtime = get_u32(record, META_MTIME_OFFSET);
+    return true;
+}
+
+/// Stores the kind, mode, owner and times of stat in the record of its entry.
+/// Does nothing if the image has no metadata table.
+pub fn meta_write<D: BlockDevice>(dev: &mut D, geometry: &FsGeometry, stat: &Stat) {
+    if !geometry.has_meta() {
+        return;
+    }
+    let (sector_id, offset) = record_location(geometry, stat.entry_offset as usize);
+    let mut sector = [0;SECTOR_SIZE];
+    dev.read_sector(sector_id, &mut sector);
+    {
+        let record = &mut sector[offset..offset+META_RECORD_SIZE];
+        record[META_FLAGS_OFFSET] = META_USED;
+        record[META_KIND_OFFSET] = stat.kind as u8;
+        set_u16(record, META_MODE_OFFSET, stat.mode);
+        set_u16(record, META_UID_OFFSET, stat.uid);
+        set_u32(record, META_CTIME_OFFSET, stat.ctime);
+        set_u32(record, META_MTIME_OFFSET, stat.mtime);
+    }
+    dev.write_sector(sector_id, &sector);
+}
+
+/// Creates the missing records of the files of the root directory, which
+/// were added since the last call. They are created at the time now and
+/// programs are made executable. Returns the number of records created.
+pub fn meta_stamp<D: BlockDevice>(dev: &mut D, geometry: &FsGeometry, now: u32) -> usize {
+    if !geometry.has_meta() {
+        return 0;
+    }
+    let mut cnt = 0;
+    let mut sector = [0;SECTOR_SIZE];
+    let mut data = [0;SECTOR_SIZE];
+    let root_sector = (geometry.root_entry * geometry.sectors_per_block()) as u32;
+    for pos in (0..geometry.entries() * ENTRY_SIZE).step_by(ENTRY_SIZE) {
+        dev.read_sector(root_sector + (pos / SECTOR_SIZE) as u32, &mut sector);
+        let entry = &sector[pos % SECTOR_SIZE..pos % SECTOR_SIZE + ENTRY_SIZE];
+        if entry[0] == 0 {
+            break;
+        }
+        let mut stat = Stat::null();
+        stat.entry_offset = pos as u16;
+        if meta_read(dev, geometry, &mut stat) {
+            continue;
+        }
+        let start = get_u16(entry, ENTRY_START_OFFSET) as usize;
+        let size = get_u32(entry, ENTRY_SIZE_OFFSET) as usize;
+        let mut exec = false;
+        if size >= 4 && start != 0 && start < geometry.blocks() {
+            dev.read_sector((start * geometry.sectors_per_block()) as u32, &mut data);
+            exec = get_u32(&data, 0) == EXEC_MAGIC;
+        }
+        stat.mode = if exec { DEFAULT_EXEC_MODE } else { DEFAULT_MODE };
+        stat.ctime = now;
+        stat.mtime = now;
+        meta_write(dev, geometry, &stat);
+        cnt += 1;
+    }
+    return cnt;
+}
+
+// Sector and offset in the sector of the record of the entry at entry_pos
+// in the root directory
+fn record_location(geometry: &FsGeometry, entry_pos: usize) -> (u32, usize) {
+    let pos = entry_pos / ENTRY_SIZE * META_RECORD_SIZE;
+    (geometry.meta_start + (pos / SECTOR_SIZE) as u32, pos % SECTOR_SIZE)
+}
\ No newline at end of file
@@ -0,0 +1,46 @@
+// Protection of the mapped pages
+pub const PROT_NONE: u32 = 0x0;
+pub const PROT_READ: u32 = 0x1;
+pub const PROT_WRITE: u32 = 0x2;
+pub const PROT_EXEC: u32 = 0x4;
+
+// Mapping flags
+pub const MAP_SHARED: u32 = 0x01;
+pub const MAP_PRIVATE: u32 = 0x02;
+pub const MAP_FIXED: u32 = 0x10;
+pub const MAP_ANONYMOUS: u32 = 0x20;
+
+/// Returned by mmap when the mapping failed
+pub const MAP_FAILED: u32 = 0xffffffff;
+
+/// Arguments of the Mmap syscall, they do not fit in the syscall registers.
+/// fd and offset are ignored by MAP_ANONYMOUS mappings.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct MmapArgs {
+    pub addr: u32,
+    pub len: usize,
+    pub prot: u32,
+    pub flags: u32,
+    pub fd: i32,
+    pub offset: u32
+}
+
+// Layout of the user address space: nothing is mapped below the program,
+// loaded at USER_CODE_START, so that null pointers fault. The frames given by
+// AllocFrame are mapped between USER_FRAMES_START and USER_HEAP_START. The program break
+// starts at USER_HEAP_START and cannot reach the area of the mappings, which
+// ends below the guard page of the stack.
+pub const USER_CODE_START: u32 = 0x400000;
+pub const USER_FRAMES_START: u32 = 0xC000000;
+pub const USER_HEAP_START: u32 = 0x10000000;
+pub const USER_MMAP_START: u32 = 0x40000000;
+pub const USER_MMAP_END: u32 = USER_STACK_TOP - USER_STACK_MAX as u32 - 0x1000;
+
+// The user stack ends at USER_STACK_TOP, right below the kernel. exec maps
+// USER_STACK_SIZE bytes, then the stack grows down on demand up to
+// USER_STACK_MAX bytes. The page below this limit is a guard page which is
+// never mapped.
+pub const USER_STACK_TOP: u32 = 0xC0000000;
+pub const USER_STACK_SIZE: usize = 0x10000;
+pub const USER_STACK_MAX: usize = 0x100000;
\ No newline at end of file
@@ -0,0 +1,5 @@
+// Kernel state is exposed under this directory
+pub const PROC_PATH: &str = "/proc/";
+
+/// Maximum number of tasks alive at the same time
+pub const MAX_TASKS: usize = 8;
\ No newline at end of file
@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum Syscall {
     Puts            = 0x00,
@@ -19,5 +20,57 @@ pub enum Syscall {
     CursorDisable   = 0x10,
     CopyScr         = 0x11,
     AllocFrame      = 0x12,
-    FreeFrame       = 0x13
-}
\ No newline at end of file
+    FreeFrame       = 0x13,
+    FileWrite       = 0x14,
+    FileIoctl       = 0x15,
+    Brk             = 0x16,
+    Sbrk            = 0x17,
+    Mmap            = 0x18,
+    Munmap          = 0x19,
+    Mprotect        = 0x1a,
+    Msync           = 0x1b,
+    ShmOpen         = 0x1c
+}
+
+impl Syscall {
+    /// System call of the number nb, None if there is none
+    pub fn from_u32(nb: u32) -> Option<Syscall> {
+        let syscall = match nb {
+            0x00 => Syscall::Puts,
+            0x01 => Syscall::Putc,
+            0x02 => Syscall::Exec,
+            0x03 => Syscall::Keypressed,
+            0x04 => Syscall::Getc,
+            0x05 => Syscall::FileStat,
+            0x06 => Syscall::FileOpen,
+            0x07 => Syscall::FileClose,
+            0x08 => Syscall::FileRead,
+            0x09 => Syscall::FileSeek,
+            0x0a => Syscall::FileIterator,
+            0x0b => Syscall::FileNext,
+            0x0c => Syscall::GetTicks,
+            0x0d => Syscall::Sleep,
+            0x0e => Syscall::SetCursor,
+            0x0f => Syscall::GetCursor,
+            0x10 => Syscall::CursorDisable,
+            0x11 => Syscall::CopyScr,
+            0x12 => Syscall::AllocFrame,
+            0x13 => Syscall::FreeFrame,
+            0x14 => Syscall::FileWrite,
+            0x15 => Syscall::FileIoctl,
+            0x16 => Syscall::Brk,
+            0x17 => Syscall::Sbrk,
+            0x18 => Syscall::Mmap,
+            0x19 => Syscall::Munmap,
+            0x1a => Syscall::Mprotect,
+            0x1b => Syscall::Msync,
+            0x1c => Syscall::ShmOpen,
+            _ => return None
+        };
+        Some(syscall)
+    }
+}
+
+/// Flag of Exec: the system calls of the program and of the programs it runs
+/// are logged to /proc/trace
+pub const EXEC_TRACE: u32 = 0x1;
\ No newline at end of file
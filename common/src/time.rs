@@ -0,0 +1,50 @@
+const SECS_PER_DAY: u32 = 86400;
+
+/// Calendar date and time in UTC
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32
+}
+
+impl DateTime {
+    /// Converts a number of seconds since 1970-01-01 00:00:00 into a date
+    pub fn from_timestamp(timestamp: u32) -> DateTime {
+        let days = timestamp / SECS_PER_DAY;
+        let secs = timestamp % SECS_PER_DAY;
+        // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719468;
+        let era = z / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        DateTime {
+            year: year,
+            month: month,
+            day: day,
+            hour: secs / 3600,
+            minute: (secs % 3600) / 60,
+            second: secs % 60
+        }
+    }
+
+    /// Converts the date into a number of seconds since 1970-01-01 00:00:00
+    pub fn to_timestamp(&self) -> u32 {
+        let year = if self.month <= 2 { self.year - 1 } else { self.year };
+        let era = year / 400;
+        let yoe = year - era * 400;
+        let mp = if self.month > 2 { self.month - 3 } else { self.month + 9 };
+        let doy = (153 * mp + 2) / 5 + self.day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146097 + doe - 719468;
+        days * SECS_PER_DAY + self.hour * 3600 + self.minute * 60 + self.second
+    }
+}
\ No newline at end of file
@@ -54,11 +54,11 @@ entrypoint:
         shr ecx, 12
         and ecx, 0x3ff
         ; map first MB in lower-half
-        mov [low_kernel_pt + ecx * 4], eax
-        or dword [low_kernel_pt + ecx * 4], 0x3
+        mov [low_kernel_pt + ecx * PAGE_ENTRY_SIZE], eax
+        or dword [low_kernel_pt + ecx * PAGE_ENTRY_SIZE], 0x3
         ; map first MB in higher-half
-        mov [kernel_pt + ecx * 4], eax
-        or dword [kernel_pt + ecx * 4], 0x3 
+        mov [kernel_pt + ecx * PAGE_ENTRY_SIZE], eax
+        or dword [kernel_pt + ecx * PAGE_ENTRY_SIZE], 0x3 
         
         add eax, 0x1000
         cmp eax, low_kernel_end
@@ -66,8 +66,14 @@ entrypoint:
         
     ; map higher-half kernel pt in pd
     mov eax, kernel_pt
-    mov [page_directory + KERNEL_PAGE_NUMBER * 4], eax
-    or dword [page_directory + KERNEL_PAGE_NUMBER * 4], 0x3
+    mov [page_directory + KERNEL_PAGE_NUMBER * PAGE_ENTRY_SIZE], eax
+    or dword [page_directory + KERNEL_PAGE_NUMBER * PAGE_ENTRY_SIZE], 0x3
+%ifdef PAE
+    ; the second half of kernel_pt maps the next 2 MB
+    mov eax, kernel_pt + 0x1000
+    mov [page_directory + (KERNEL_PAGE_NUMBER + 1) * PAGE_ENTRY_SIZE], eax
+    or dword [page_directory + (KERNEL_PAGE_NUMBER + 1) * PAGE_ENTRY_SIZE], 0x3
+%endif
     
     mov eax, kernel_start
     .high_kernel_pt_init:
@@ -77,15 +83,36 @@ entrypoint:
         
         mov ebx, eax 
         sub ebx, KERNEL_BASE
-        mov [kernel_pt + ecx * 4], ebx
-        or dword [kernel_pt + ecx * 4], 0x3
+        mov [kernel_pt + ecx * PAGE_ENTRY_SIZE], ebx
+        or dword [kernel_pt + ecx * PAGE_ENTRY_SIZE], 0x3
         
         add eax, 0x1000
         cmp eax, kernel_end
         jl .high_kernel_pt_init
         
+%ifdef PAE
+    ; each entry of the page directory pointer table maps 1 GB with one of
+    ; the four pages of page_directory
+    mov eax, page_directory
+    or eax, 0x1
+    mov ecx, 0
+    .pdpt_init:
+        mov [page_directory_pointer_table + ecx * 8], eax
+        add eax, 0x1000
+        inc ecx
+        cmp ecx, 4
+        jl .pdpt_init
+        
+    mov eax, cr4
+    or eax, 1 << 5      ; set PAE
+    mov cr4, eax
+    
+    ; init paging
+    mov eax, page_directory_pointer_table
+%else
     ; init paging
     mov eax, page_directory
+%endif
     mov cr3, eax
     mov eax, cr0
     or eax, 0x80000000
@@ -108,10 +135,19 @@ section .low_bss nobits
 alignb 4096
 page_directory:
     resd 1024
+%ifdef PAE
+    resd 3 * 1024       ; the three other page directories
+page_directory_pointer_table:
+    resq 4
+alignb 4096
+%endif
 low_kernel_pt:
     resd 1024
 kernel_pt:
     resd 1024
+%ifdef PAE
+    resd 1024           ; kernel_pt maps 4 MB with two PAE page tables
+%endif
 
 ;-------------------------------------------------------------------------------
 section .text
@@ -128,9 +164,11 @@ higher_half:
     push ebx
     push dword [multiboot_magic]
     
-    ; unmap lower-half kernel
+    ; unmap lower-half kernel, then flush its entries from the TLB
     mov eax, 0
     mov [page_directory], eax
+    mov eax, cr3
+    mov cr3, eax
     
     call kmain
     
@@ -0,0 +1,260 @@
+//! Device files exposed under /dev
+#![allow(dead_code)]
+
+use core::mem::size_of;
+use core::cmp::min;
+use rlibc::memcpy;
+use vga::*;
+use pio::*;
+use keyboard::*;
+use ide::*;
+use timer::get_ticks;
+use fs::SB;
+use common::*;
+
+const DEVICES_NB: usize = 7;
+const DEVICES: [(&str, Device);DEVICES_NB] = [
+    ("console", Device::Console),
+    ("kbd", Device::Kbd),
+    ("vga", Device::Vga),
+    ("hda", Device::Hda),
+    ("null", Device::Null),
+    ("zero", Device::Zero),
+    ("random", Device::Random)
+];
+
+static mut RANDOM_STATE: u32 = 0;
+// size of the first disk, reported by IDENTIFY
+static mut HDA_SECTORS: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Device {
+    Console,
+    Kbd,
+    Vga,
+    Hda,
+    Null,
+    Zero,
+    Random
+}
+
+/// Reads the size of the disks, done before the file system is used
+pub fn dev_init() {
+    unsafe { HDA_SECTORS = ide_identify(IDE_MASTER).unwrap_or(0); }
+}
+
+/// Returns the device associated to a path of the form /dev/<name>
+pub fn dev_lookup(filename: &str) -> Option<Device> {
+    if !filename.starts_with(DEV_PATH) {
+        return None;
+    }
+    let name = &filename[DEV_PATH.len()..];
+    for &(dev_name, dev) in DEVICES.iter() {
+        if dev_name == name {
+            return Some(dev);
+        }
+    }
+    return None;
+}
+
+/// Size in bytes of the device, 0 for stream devices
+pub fn dev_size(dev: Device) -> usize {
+    match dev {
+        Device::Vga => size_of::<FrameBuffer>(),
+        Device::Hda => unsafe { HDA_SECTORS as usize * SECTOR_SIZE },
+        _ => 0
+    }
+}
+
+/// Reads at most n bytes from the device at the position pos.
+/// Returns the number of bytes read, 0 at the end of the device.
+pub fn dev_read(dev: Device, pos: usize, buf: *mut u8, n: usize) -> Result<usize, Error> {
+    unsafe {
+        match dev {
+            Device::Console => {
+                let mut cnt = 0;
+                while cnt < n {
+                    let c = getc() as u8;
+                    vga_write_byte(c);
+                    *buf.offset(cnt as isize) = c;
+                    cnt += 1;
+                    if c == b'\n' {
+                        break;
+                    }
+                }
+                Ok(cnt)
+            }
+            Device::Kbd => {
+                let mut cnt = 0;
+                while cnt < n && (cnt == 0 || keypressed()) {
+                    *buf.offset(cnt as isize) = getc() as u8;
+                    cnt += 1;
+                }
+                Ok(cnt)
+            }
+            Device::Vga => {
+                let size = dev_size(dev);
+                if pos >= size {
+                    return Ok(0);
+                }
+                let cnt = min(n, size - pos);
+                memcpy(buf, vga_raw_buffer().offset(pos as isize), cnt);
+                Ok(cnt)
+            }
+            Device::Hda => {
+                let size = dev_size(dev);
+                if pos >= size {
+                    return Ok(0);
+                }
+                let n = min(n, size - pos);
+                let mut sector : [u16;SECTOR_SIZE/2] = [0;SECTOR_SIZE/2];
+                let mut cnt = 0;
+                while cnt < n {
+                    let offset = (pos + cnt) % SECTOR_SIZE;
+                    let len = min(n - cnt, SECTOR_SIZE - offset);
+                    read_sector(((pos + cnt) / SECTOR_SIZE) as u32, &mut sector[0] as *mut u16);
+                    memcpy(buf.offset(cnt as isize), (&sector[0] as *const u16 as *const u8).offset(offset as isize), len);
+                    cnt += len;
+                }
+                Ok(cnt)
+            }
+            Device::Null => Ok(0),
+            Device::Zero => {
+                for i in 0..n {
+                    *buf.offset(i as isize) = 0;
+                }
+                Ok(n)
+            }
+            Device::Random => {
+                for i in 0..n {
+                    *buf.offset(i as isize) = random() as u8;
+                }
+                Ok(n)
+            }
+        }
+    }
+}
+
+/// Writes n bytes to the device at the position pos.
+/// Returns the number of bytes written, ReadOnly for the keyboard and
+/// NoSpace at the end of the disk. The sectors of the disk holding the
+/// metadata of the file system are written in a single transaction, NoSpace
+/// is also returned if they do not fit in the journal.
+pub fn dev_write(dev: Device, pos: usize, buf: *const u8, n: usize) -> Result<usize, Error> {
+    unsafe {
+        match dev {
+            Device::Console => {
+                for i in 0..n {
+                    vga_write_byte(*buf.offset(i as isize));
+                }
+                Ok(n)
+            }
+            Device::Vga => {
+                let size = dev_size(dev);
+                if pos >= size {
+                    return Ok(0);
+                }
+                let cnt = min(n, size - pos);
+                memcpy(vga_raw_buffer().offset(pos as isize), buf, cnt);
+                Ok(cnt)
+            }
+            Device::Hda => {
+                let size = dev_size(dev);
+                if pos >= size {
+                    return Err(Error::NoSpace);
+                }
+                let n = min(n, size - pos);
+                if n == 0 {
+                    return Ok(0);
+                }
+                let geometry = SB.geometry();
+                let mut disk = IdeDisk;
+                let mut journal = Journal::begin(&mut disk, &geometry);
+                // the data sectors are written directly, so a write whose
+                // metadata would not fit in the journal is refused up front
+                let first = (pos / SECTOR_SIZE) as u32;
+                let last = ((pos + n - 1) / SECTOR_SIZE) as u32;
+                let metadata = (first..last + 1).filter(|&sector_id| geometry.is_metadata(sector_id)).count();
+                if journal.capacity() != 0 && metadata > journal.capacity() {
+                    return Err(Error::NoSpace);
+                }
+                let mut sector = [0;SECTOR_SIZE];
+                let mut cnt = 0;
+                while cnt < n {
+                    let sector_id = ((pos + cnt) / SECTOR_SIZE) as u32;
+                    let offset = (pos + cnt) % SECTOR_SIZE;
+                    let len = min(n - cnt, SECTOR_SIZE - offset);
+                    // partial sectors must be read first to keep the rest of their content
+                    if len != SECTOR_SIZE {
+                        journal.read_sector(sector_id, &mut sector);
+                    }
+                    memcpy(&mut sector[offset], buf.offset(cnt as isize), len);
+                    if geometry.is_metadata(sector_id) {
+                        journal.write_sector(sector_id, &sector);
+                    } else {
+                        IdeDisk.write_sector(sector_id, &sector);
+                    }
+                    cnt += len;
+                }
+                journal.commit()?;
+                Ok(cnt)
+            }
+            Device::Null | Device::Zero | Device::Random => Ok(n),
+            Device::Kbd => Err(Error::ReadOnly)
+        }
+    }
+}
+
+/// Device specific control requests. The meaning of arg and of the
+/// returned value depends on the request.
+pub fn dev_ioctl(dev: Device, request: u32, arg: u32) -> Result<u32, Error> {
+    match (dev, request) {
+        (Device::Console, IOCTL_SET_CURSOR) | (Device::Vga, IOCTL_SET_CURSOR) => {
+            let (x, y) = arg_to_cursor(arg);
+            vga_set_cursor(x as usize, y as usize);
+            Ok(0)
+        }
+        (Device::Console, IOCTL_GET_CURSOR) | (Device::Vga, IOCTL_GET_CURSOR) => {
+            let (x, y) = vga_get_cursor();
+            Ok(cursor_to_arg(x as u32, y as u32))
+        }
+        (Device::Console, IOCTL_CURSOR_DISABLE) | (Device::Vga, IOCTL_CURSOR_DISABLE) => {
+            if arg == 0 {
+                enable_cursor();
+            } else {
+                disable_cursor();
+            }
+            Ok(0)
+        }
+        (Device::Console, IOCTL_SET_COLOR) | (Device::Vga, IOCTL_SET_COLOR) => {
+            vga_set_color(Color::from_u32(arg >> 4), Color::from_u32(arg & 0xf));
+            Ok(0)
+        }
+        (Device::Console, IOCTL_CLEAR) | (Device::Vga, IOCTL_CLEAR) => {
+            vga_clear();
+            Ok(0)
+        }
+        (Device::Console, IOCTL_KEYPRESSED) | (Device::Kbd, IOCTL_KEYPRESSED) => {
+            Ok(keypressed() as u32)
+        }
+        (Device::Hda, IOCTL_SECTOR_SIZE) => Ok(SECTOR_SIZE as u32),
+        (Device::Random, IOCTL_SEED) => {
+            unsafe { RANDOM_STATE = arg; }
+            Ok(0)
+        }
+        _ => Err(Error::Invalid)
+    }
+}
+
+// xorshift32 pseudo-random generator, lazily seeded with the timer
+fn random() -> u32 {
+    unsafe {
+        if RANDOM_STATE == 0 {
+            RANDOM_STATE = get_ticks() | 1;
+        }
+        RANDOM_STATE ^= RANDOM_STATE << 13;
+        RANDOM_STATE ^= RANDOM_STATE >> 17;
+        RANDOM_STATE ^= RANDOM_STATE << 5;
+        return RANDOM_STATE;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,364 @@
+//! Physical frame allocator built from the multiboot memory map.
+//! A bitmap keeps the state of every frame of the physical address space,
+//! 4 GB or 16 GB with the pae feature: frames outside of the available
+//! regions are marked as used once and for all, so only the truly usable RAM
+//! is ever handed out.
+//! Free frames are grouped in blocks of 2^order contiguous frames managed
+//! by a buddy allocator. The free lists are stored in the first frame of
+//! each free block, which is accessed through a one page window.
+#![allow(dead_code)]
+
+use core::mem::size_of;
+use rlibc::memset;
+use multiboot::*;
+use paging::*;
+use vga::*;
+
+pub const MAX_ORDER: usize = 10;
+
+#[cfg(not(feature = "pae"))]
+const FRAMES_NB: usize = 0x100000;
+#[cfg(feature = "pae")]
+const FRAMES_NB: usize = 0x400000;
+const MEMORY_END: u64 = (FRAMES_NB as u64) << 12;
+const BITMAP_SIZE: usize = FRAMES_NB / 32;
+// BIOS data, real mode IVT, VGA memory and ROMs
+const LOW_MEMORY_END: u32 = 0x100000;
+// last page of the virtual address space
+const WINDOW_ADDR: u32 = 0xFFFFF000;
+const FREE_BLOCK_MAGIC: u32 = 0xf4eeb10c;
+
+static mut FRAME_BITMAP: [u32;BITMAP_SIZE] = [0;BITMAP_SIZE];
+static mut FRAMES_TOTAL: usize = 0;
+static mut FRAMES_FREE: usize = 0;
+static mut FREE_LISTS: [PhysAddr;MAX_ORDER+1] = [0;MAX_ORDER+1];
+// the free lists are built at the first allocation
+static mut BUDDY_READY: bool = false;
+static mut WINDOW_TABLE: PageTable = PageTable::null();
+static mut WINDOW_FRAME: PhysAddr = 0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    pub blocks: [usize;MAX_ORDER+1]
+}
+
+// Header written at the beginning of each free block
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct FreeBlock {
+    magic: u32,
+    order: u32,
+    next: PhysAddr,
+    previous: PhysAddr
+}
+
+pub fn frame_init(mboot: &MultibootInfo, mboot_addr: u32) {
+    unsafe {
+        for word in FRAME_BITMAP.iter_mut() {
+            *word = 0xffffffff;
+        }
+        let mapped_end = phys!(get_kernel_end());
+        if mboot.flags & MULTIBOOT_INFO_MEM_MAP != 0 && mboot.mmap_addr + mboot.mmap_length <= mapped_end {
+            let mut addr = mboot.mmap_addr;
+            while addr < mboot.mmap_addr + mboot.mmap_length {
+                let entry = *(virt!(addr) as *const MultibootMmapEntry);
+                if entry.kind == MULTIBOOT_MEMORY_AVAILABLE && entry.addr < MEMORY_END {
+                    let end = if entry.addr + entry.len > MEMORY_END { MEMORY_END } else { entry.addr + entry.len };
+                    // only frames entirely inside the region can be used
+                    let first = ((entry.addr + FRAME_SIZE as u64 - 1) >> 12) as usize;
+                    let last = (end >> 12) as usize;
+                    for frame in first..last {
+                        release(frame);
+                    }
+                }
+                addr += entry.size + size_of::<u32>() as u32;
+            }
+        } else if mboot.flags & MULTIBOOT_INFO_MEMORY != 0 {
+            // without memory map, mem_upper is the size in kB of the memory above 1 MB
+            let last = (LOW_MEMORY_END as usize + mboot.mem_upper as usize * 1024) / FRAME_SIZE;
+            for frame in (LOW_MEMORY_END as usize / FRAME_SIZE)..last {
+                release(frame);
+            }
+        }
+        FRAMES_TOTAL = FRAMES_FREE;
+        
+        // the kernel is loaded right after the low memory
+        frame_reserve_area(0, mapped_end as PhysAddr);
+        frame_reserve_area(mboot_addr as PhysAddr, (mboot_addr + size_of::<MultibootInfo>() as u32) as PhysAddr);
+        if mboot.flags & MULTIBOOT_INFO_MEM_MAP != 0 {
+            frame_reserve_area(mboot.mmap_addr as PhysAddr, (mboot.mmap_addr + mboot.mmap_length) as PhysAddr);
+        }
+        
+        // the window is shared by all the directories like the rest of the kernel
+        INITIAL_PD.set_table(DIRECTORY_FSIZE - 1, WINDOW_TABLE.as_ptr(), KERNEL_MODE);
+    }
+}
+
+/// Returns the physical address of a free frame, 0 if the memory is full
+pub fn frame_alloc() -> PhysAddr {
+    frame_alloc_order(0)
+}
+
+/// Returns the physical address of 2^order contiguous free frames aligned on
+/// their size, 0 if there is no such area
+pub fn frame_alloc_order(order: usize) -> PhysAddr {
+    unsafe {
+        let addr = buddy_alloc(order);
+        if addr != 0 {
+            for frame in 0..(1 << order) {
+                reserve(frame_index(addr) + frame);
+            }
+        }
+        return addr;
+    }
+}
+
+/// Returns the physical address of frames contiguous free frames, 0 if there
+/// is no such area. The end of the block of the smallest order holding them
+/// goes back to the free lists as blocks of the lower orders. The frames are
+/// given back one by one with frame_free.
+pub fn frame_alloc_exact(frames: usize) -> PhysAddr {
+    let order = frame_order(frames);
+    if order > MAX_ORDER {
+        return 0;
+    }
+    unsafe {
+        let addr = buddy_alloc(order);
+        if addr == 0 {
+            return 0;
+        }
+        // the largest aligned blocks that fit between frames and the end
+        let mut frame = frames;
+        while frame < (1 << order) {
+            let mut piece = 0;
+            while frame % (1 << (piece + 1)) == 0 && frame + (1 << (piece + 1)) <= (1 << order) {
+                piece += 1;
+            }
+            list_push(addr + frame_addr(frame), piece);
+            frame += 1 << piece;
+        }
+        for frame in 0..frames {
+            reserve(frame_index(addr) + frame);
+        }
+        return addr;
+    }
+}
+
+pub fn frame_free(addr: PhysAddr) {
+    frame_free_order(addr, 0);
+}
+
+/// Gives back 2^order frames allocated with frame_alloc_order. They can also be
+/// given back one by one with frame_free.
+pub fn frame_free_order(addr: PhysAddr, order: usize) {
+    let first = frame_index(addr);
+    for frame in first..(first + (1 << order)) {
+        if frame_is_free(frame_addr(frame)) {
+            println!("frame_free: frame {:#x} is not allocated", frame_addr(frame));
+            return;
+        }
+    }
+    unsafe {
+        for frame in first..(first + (1 << order)) {
+            release(frame);
+        }
+        if BUDDY_READY {
+            buddy_free(addr, order);
+        }
+    }
+}
+
+/// Marks the frames between the physical addresses start and end as used.
+/// Reservations must be done before the first allocation.
+pub fn frame_reserve_area(start: PhysAddr, end: PhysAddr) {
+    if unsafe { BUDDY_READY } {
+        println!("frame_reserve_area: frames already handed out");
+        return;
+    }
+    let first = frame_index(start);
+    let last = frame_index(end + FRAME_SIZE as PhysAddr - 1);
+    for frame in first..last {
+        unsafe { reserve(frame); }
+    }
+}
+
+/// Number of contiguous free frames starting at the physical address start,
+/// without going past the physical address limit
+pub fn frame_free_area(start: PhysAddr, limit: u64) -> usize {
+    let first = frame_index(start);
+    let mut frame = first;
+    while (frame as u64) < limit >> 12 && frame < FRAMES_NB && frame_is_free(frame_addr(frame)) {
+        frame += 1;
+    }
+    return frame - first;
+}
+
+/// Fills the frame at the physical address addr with zeros, it does not
+/// need to be mapped anywhere
+pub fn frame_zero(addr: PhysAddr) {
+    unsafe { memset(frame_map(addr), 0, FRAME_SIZE); }
+}
+
+/// Gives access to the content of the frame at the physical address addr.
+/// The pointer is valid until the next call to a frame function.
+pub fn frame_map(addr: PhysAddr) -> *mut u8 {
+    unsafe { map_window(addr) as *mut u8 }
+}
+
+pub fn frame_is_free(addr: PhysAddr) -> bool {
+    let frame = frame_index(addr);
+    unsafe { FRAME_BITMAP[frame / 32] & (1 << (frame % 32)) == 0 }
+}
+
+/// Smallest order of a block holding the given number of frames
+pub fn frame_order(frames: usize) -> usize {
+    let mut order = 0;
+    while (1 << order) < frames {
+        order += 1;
+    }
+    return order;
+}
+
+pub fn frame_stats() -> FrameStats {
+    unsafe {
+        let mut stats = FrameStats { total: FRAMES_TOTAL, free: FRAMES_FREE, blocks: [0;MAX_ORDER+1] };
+        for order in 0..(MAX_ORDER + 1) {
+            let mut addr = FREE_LISTS[order];
+            while addr != 0 {
+                stats.blocks[order] += 1;
+                addr = (*map_window(addr)).next;
+            }
+        }
+        return stats;
+    }
+}
+
+// Splits the free frames of the bitmap in the largest possible blocks
+unsafe fn buddy_init() {
+    let mut frame = 0;
+    let mut run = 0;
+    while frame < FRAMES_NB {
+        if run == 0 {
+            run = frame_free_area(frame_addr(frame), MEMORY_END);
+            if run == 0 {
+                frame += 1;
+                continue;
+            }
+        }
+        let mut order = 0;
+        while order < MAX_ORDER && frame % (1 << (order + 1)) == 0 && run >= (1 << (order + 1)) {
+            order += 1;
+        }
+        list_push(frame_addr(frame), order);
+        frame += 1 << order;
+        run -= 1 << order;
+    }
+    BUDDY_READY = true;
+}
+
+// Takes a block of 2^order frames from the free lists, splitting a block of a
+// higher order if needed. The frames stay free in the bitmap.
+unsafe fn buddy_alloc(order: usize) -> PhysAddr {
+    if !BUDDY_READY {
+        buddy_init();
+    }
+    let mut current = order;
+    while current <= MAX_ORDER && FREE_LISTS[current] == 0 {
+        current += 1;
+    }
+    if current > MAX_ORDER {
+        return 0;
+    }
+    let addr = FREE_LISTS[current];
+    list_remove(addr, current);
+    // the upper halves go back to the lower orders
+    while current > order {
+        current -= 1;
+        list_push(addr + frame_addr(1 << current), current);
+    }
+    return addr;
+}
+
+// Merges the block with its buddies as long as they are free
+unsafe fn buddy_free(addr: PhysAddr, order: usize) {
+    let mut addr = addr;
+    let mut order = order;
+    while order < MAX_ORDER {
+        let buddy = addr ^ frame_addr(1 << order);
+        // the first frame of a free buddy is always the head of a free block
+        if !frame_is_free(buddy) || (*map_window(buddy)).order != order as u32 {
+            break;
+        }
+        list_remove(buddy, order);
+        if buddy < addr {
+            addr = buddy;
+        }
+        order += 1;
+    }
+    list_push(addr, order);
+}
+
+unsafe fn list_push(addr: PhysAddr, order: usize) {
+    let head = FREE_LISTS[order];
+    if head != 0 {
+        (*map_window(head)).previous = addr;
+    }
+    *map_window(addr) = FreeBlock {
+        magic: FREE_BLOCK_MAGIC,
+        order: order as u32,
+        next: head,
+        previous: 0
+    };
+    FREE_LISTS[order] = addr;
+}
+
+unsafe fn list_remove(addr: PhysAddr, order: usize) {
+    let block = *map_window(addr);
+    if block.magic != FREE_BLOCK_MAGIC {
+        println!("frame: corrupted free block at {:#x}", addr);
+    }
+    (*map_window(addr)).magic = 0;
+    if block.previous != 0 {
+        (*map_window(block.previous)).next = block.next;
+    } else {
+        FREE_LISTS[order] = block.next;
+    }
+    if block.next != 0 {
+        (*map_window(block.next)).previous = block.previous;
+    }
+}
+
+// Maps the frame at the physical address addr in the window
+unsafe fn map_window(addr: PhysAddr) -> *mut FreeBlock {
+    if WINDOW_FRAME != addr {
+        WINDOW_TABLE.set(TABLE_FSIZE - 1, addr | 0x3);
+        invalidate_page(WINDOW_ADDR);
+        WINDOW_FRAME = addr;
+    }
+    WINDOW_ADDR as *mut FreeBlock
+}
+
+// Physical address of the frame number frame
+fn frame_addr(frame: usize) -> PhysAddr {
+    (frame as PhysAddr) << 12
+}
+
+fn frame_index(addr: PhysAddr) -> usize {
+    (addr >> 12) as usize
+}
+
+unsafe fn reserve(frame: usize) {
+    if FRAME_BITMAP[frame / 32] & (1 << (frame % 32)) == 0 {
+        FRAME_BITMAP[frame / 32] |= 1 << (frame % 32);
+        FRAMES_FREE -= 1;
+    }
+}
+
+unsafe fn release(frame: usize) {
+    if FRAME_BITMAP[frame / 32] & (1 << (frame % 32)) != 0 {
+        FRAME_BITMAP[frame / 32] &= !(1 << (frame % 32));
+        FRAMES_FREE += 1;
+    }
+}
\ No newline at end of file
@@ -2,26 +2,41 @@
 
 use core::str;
 use core::mem;
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::cmp::min;
 use rlibc::memcpy;
 use ide::*;
+use dev::*;
+use proc::*;
+use shm::*;
+use slab::SlabCache;
+use task::current_task;
+use rtc::{boot_time, rtc_time};
 use vga::*;
 use common::*;
 
 const FDT_SIZE : usize = 128;
-const ENTRY_SIZE : usize = 32;
 pub const TYPE_TEXT: i32 = 0;
 pub const TYPE_EXEC: i32 = 1;
+pub const TYPE_DEV: i32 = 2;
 
-pub static mut FDT: Fdt = [FdtEntry::null();FDT_SIZE];
+// entries of the open files, null for the free descriptors
+pub static mut FDT: Fdt = [0 as *mut FdtEntry;FDT_SIZE];
+static mut FD_CACHE: SlabCache = SlabCache::new("fd", size_of::<FdtEntry>());
 pub static mut SB : Superblock = Superblock::null();
 
-pub type Fdt = [FdtEntry; FDT_SIZE];
+pub type Fdt = [*mut FdtEntry; FDT_SIZE];
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct FdtEntry {
     pub stat: Stat,
-    pub pos: usize
+    pub pos: usize,
+    pub dev: Option<Device>,
+    pub proc: Option<ProcFile>,
+    pub shm: Option<usize>,
+    pub task: i8
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -29,7 +44,11 @@ pub struct FdtEntry {
 pub struct Superblock {
     pub block_size: usize,
     pub fat_size: usize,
-    pub root_entry: usize
+    pub root_entry: usize,
+    pub journal_start: u32,
+    pub journal_sectors: u32,
+    pub meta_start: u32,
+    pub meta_sectors: u32
 }
 
 pub trait StatBuilder {
@@ -47,29 +66,77 @@ pub fn file_exists(filename: &str) -> bool {
     let mut it = FileIterator::new();
     while it.has_next() {
         it.next(&mut raw_filename[0]);
-        let it_filename = bytes_to_str(&raw_filename);
-        if it_filename == filename {
+        if bytes_to_str(&raw_filename) == Ok(filename) {
             return true;
         }
     }
     return false;
 }
 
-pub fn file_open(filename: &str) -> i32 {
+pub fn file_open(filename: &str) -> Result<i32, Error> {
     unsafe {
-        if file_exists(filename) {
-            let fd = free_fd();
-            FDT[fd as usize].stat = Stat::new(filename);
-            return fd;
+        let dev = dev_lookup(filename);
+        let proc = proc_lookup(filename);
+        if dev.is_none() && proc.is_none() && !file_exists(filename) {
+            return Err(Error::NotFound);
         }
-        return -1;
+        let fd = fd_alloc()?;
+        (*FDT[fd as usize]).stat = Stat::new(filename);
+        (*FDT[fd as usize]).dev = dev;
+        (*FDT[fd as usize]).proc = proc;
+        (*FDT[fd as usize]).task = current_task();
+        return Ok(fd);
     }
 }
 
-pub fn file_read(fd: i32, buf: *mut u8, n: usize) -> i32 {
+/// Opens the shared memory object called name, created with size bytes if
+/// it does not exist. Its content is only accessed through mmap.
+pub fn file_open_shm(name: &str, size: usize) -> Result<i32, Error> {
     unsafe {
-        if fd < 0 || FDT[fd as usize].stat.start == 0 {
-            return -1;
+        let id = shm_open(name, size)?;
+        let fd = match fd_alloc() {
+            Ok(fd) => fd,
+            Err(err) => {
+                shm_unref(id);
+                return Err(err);
+            }
+        };
+        let len = if name.len() < MAX_FILENAME_LENGTH { name.len() } else { MAX_FILENAME_LENGTH };
+        let entry = &mut *FDT[fd as usize];
+        entry.stat.name[0..len].copy_from_slice(&name.as_bytes()[0..len]);
+        entry.stat.size = shm_size(id);
+        entry.stat.mode = 0o666;
+        entry.stat.ctime = boot_time();
+        entry.shm = Some(id);
+        entry.task = current_task();
+        return Ok(fd);
+    }
+}
+
+/// Shared memory object opened by the file descriptor
+pub fn file_shm(fd: i32) -> Option<usize> {
+    if fd_is_valid(fd) { unsafe { (*FDT[fd as usize]).shm } } else { None }
+}
+
+/// Reads at most n bytes of the file, returns the number of bytes read
+pub fn file_read(fd: i32, buf: *mut u8, n: usize) -> Result<usize, Error> {
+    unsafe {
+        if !fd_is_valid(fd) {
+            return Err(Error::BadFd);
+        }
+        if let Some(dev) = (*FDT[fd as usize]).dev {
+            let cnt = dev_read(dev, (*FDT[fd as usize]).pos, buf, n)?;
+            (*FDT[fd as usize]).pos += cnt;
+            return Ok(cnt);
+        }
+        if let Some(proc) = (*FDT[fd as usize]).proc {
+            let cnt = proc_read(proc, (*FDT[fd as usize]).pos, buf, n);
+            (*FDT[fd as usize]).pos += cnt as usize;
+            return Ok(cnt as usize);
+        }
+        // shared memory is only accessed with mmap
+        if (*FDT[fd as usize]).shm.is_some() {
+            return Err(Error::Invalid);
         }
         
         let mut sector : [u16;SECTOR_SIZE/2] = [0;SECTOR_SIZE/2];
@@ -77,109 +144,332 @@ pub fn file_read(fd: i32, buf: *mut u8, n: usize) -> i32 {
         let fat = mem::transmute::<[u16;SECTOR_SIZE/2], [u8;SECTOR_SIZE]>(sector);
         
         let mut cnt = 0;
-        let mut block = FDT[fd as usize].stat.start;
+        let mut block = (*FDT[fd as usize]).stat.start;
         let mut sector_id = block * (SB.block_size / SECTOR_SIZE);
-        let size = if FDT[fd as usize].pos + n > FDT[fd as usize].stat.size {
-            FDT[fd as usize].stat.size
+        let size = if (*FDT[fd as usize]).pos + n > (*FDT[fd as usize]).stat.size {
+            (*FDT[fd as usize]).stat.size
         } else {
-            FDT[fd as usize].pos + n
+            (*FDT[fd as usize]).pos + n
         };
         
         for i in 0..(size / SECTOR_SIZE) {
-            if i >= FDT[fd as usize].pos / SECTOR_SIZE {
+            if i >= (*FDT[fd as usize]).pos / SECTOR_SIZE {
                 sector_id += i % (SB.block_size / SECTOR_SIZE);
                 read_sector(sector_id as u32, &mut sector[0] as *mut u16);
                 let data = mem::transmute::<[u16;SECTOR_SIZE/2], [u8;SECTOR_SIZE]>(sector);
                 memcpy(buf.offset(cnt as isize), &data[0], SECTOR_SIZE);
-                FDT[fd as usize].pos += SECTOR_SIZE;
+                (*FDT[fd as usize]).pos += SECTOR_SIZE;
                 cnt += SECTOR_SIZE;
             }
-            if FDT[fd as usize].pos % SB.block_size == 0 {
+            if (*FDT[fd as usize]).pos % SB.block_size == 0 {
                 block = fat[block] as usize;
                 sector_id = block * (SB.block_size / SECTOR_SIZE);
             }
         }
         
-        if FDT[fd as usize].pos >= FDT[fd as usize].stat.size {
-            return 0;
+        if (*FDT[fd as usize]).pos >= (*FDT[fd as usize]).stat.size {
+            return Ok(0);
         } else {
             read_sector(sector_id as u32, &mut sector[0] as *mut u16);
             let data = mem::transmute::<[u16;SECTOR_SIZE/2], [u8;SECTOR_SIZE]>(sector);
-            memcpy(buf.offset(cnt as isize), &data[FDT[fd as usize].pos % SECTOR_SIZE], size % SECTOR_SIZE);
-            FDT[fd as usize].pos += size % SECTOR_SIZE;
-            return n as i32;
+            memcpy(buf.offset(cnt as isize), &data[(*FDT[fd as usize]).pos % SECTOR_SIZE], size % SECTOR_SIZE);
+            (*FDT[fd as usize]).pos += size % SECTOR_SIZE;
+            return Ok(n);
         }
     }
 }
 
-pub fn file_seek(fd: i32, offset: usize) -> i32 {
+/// Writes n bytes to the file, returns the number of bytes written.
+/// Files on the disk are overwritten in place, they never grow.
+pub fn file_write(fd: i32, buf: *const u8, n: usize) -> Result<usize, Error> {
     unsafe {
-        if FDT[fd as usize].pos + offset > FDT[fd as usize].stat.size {
-            FDT[fd as usize].pos = FDT[fd as usize].stat.size;
-            return -1;
+        if !fd_is_valid(fd) {
+            return Err(Error::BadFd);
+        }
+        if let Some(dev) = (*FDT[fd as usize]).dev {
+            let cnt = dev_write(dev, (*FDT[fd as usize]).pos, buf, n)?;
+            (*FDT[fd as usize]).pos += cnt;
+            return Ok(cnt);
+        }
+        match file_disk_stat(fd) {
+            Some(stat) => {
+                let cnt = file_write_at(&stat, (*FDT[fd as usize]).pos, buf, n)?;
+                (*FDT[fd as usize]).pos += cnt;
+                Ok(cnt)
+            }
+            // proc files and shared memory
+            None => Err(Error::ReadOnly)
+        }
+    }
+}
+
+/// Stat of a file stored on the disk, None for devices, proc files and shared memory
+pub fn file_disk_stat(fd: i32) -> Option<Stat> {
+    unsafe {
+        if !fd_is_valid(fd) || (*FDT[fd as usize]).dev.is_some() || (*FDT[fd as usize]).proc.is_some() || (*FDT[fd as usize]).shm.is_some() {
+            return None;
+        }
+        Some((*FDT[fd as usize]).stat)
+    }
+}
+
+/// Reads at most n bytes of the disk file described by stat at the position pos,
+/// without going through a file descriptor. Returns the number of bytes read.
+pub fn file_read_at(stat: &Stat, pos: usize, buf: *mut u8, n: usize) -> usize {
+    file_io_at(stat, pos, buf, n, false)
+}
+
+/// Overwrites at most n bytes of the disk file described by stat at the position
+/// pos and updates its modification time. The file never grows. Returns the
+/// number of bytes written, ReadOnly if the mode of the file does not allow
+/// writing and NoSpace at the end of the file.
+pub fn file_write_at(stat: &Stat, pos: usize, buf: *const u8, n: usize) -> Result<usize, Error> {
+    if !file_writable(stat) {
+        return Err(Error::ReadOnly);
+    }
+    if pos >= stat.size && n != 0 {
+        return Err(Error::NoSpace);
+    }
+    // the data is written in place before the metadata is committed
+    let cnt = file_io_at(stat, pos, buf as *mut u8, n, true);
+    let mut stat = *stat;
+    stat.mtime = rtc_time();
+    let geometry = unsafe { SB.geometry() };
+    let mut disk = IdeDisk;
+    let mut journal = Journal::begin(&mut disk, &geometry);
+    meta_write(&mut journal, &geometry, &stat);
+    journal.commit()?;
+    return Ok(cnt);
+}
+
+/// Whether the disk file described by stat can be written
+pub fn file_writable(stat: &Stat) -> bool {
+    stat.kind == FileKind::File && stat.mode & MODE_USER_WRITE != 0
+}
+
+pub fn file_ioctl(fd: i32, request: u32, arg: u32) -> Result<u32, Error> {
+    unsafe {
+        if !fd_is_valid(fd) {
+            return Err(Error::BadFd);
+        }
+        match (*FDT[fd as usize]).dev {
+            Some(dev) => dev_ioctl(dev, request, arg),
+            None => Err(Error::NotDevice)
+        }
+    }
+}
+
+/// Moves the position in the file forward by offset bytes. The position stops
+/// at the end of the file, which is an error.
+pub fn file_seek(fd: i32, offset: usize) -> Result<(), Error> {
+    unsafe {
+        if !fd_is_valid(fd) {
+            return Err(Error::BadFd);
+        }
+        if (*FDT[fd as usize]).dev.is_some() && (*FDT[fd as usize]).stat.size == 0 {
+            // stream devices have no end
+            (*FDT[fd as usize]).pos += offset;
+            return Ok(());
+        }
+        if (*FDT[fd as usize]).pos + offset > (*FDT[fd as usize]).stat.size {
+            (*FDT[fd as usize]).pos = (*FDT[fd as usize]).stat.size;
+            return Err(Error::Invalid);
         } else {
-            FDT[fd as usize].pos += offset;
-            return 0;
+            (*FDT[fd as usize]).pos += offset;
+            return Ok(());
         }
     }
 }
 
 pub fn rewind(fd: i32) {
     unsafe {    
-        FDT[fd as usize].pos = 0;
+        (*FDT[fd as usize]).pos = 0;
+    }
+}
+
+pub fn file_close(fd: i32) -> Result<(), Error> {
+    if !fd_is_valid(fd) {
+        return Err(Error::BadFd);
+    }
+    unsafe {
+        if let Some(id) = (*FDT[fd as usize]).shm {
+            shm_unref(id);
+        }
+        fd_free(fd);
+    }
+    return Ok(());
+}
+
+/// Closes all the files left open by a task
+pub fn file_close_all(task: i8) {
+    unsafe {
+        for fd in 0..FDT_SIZE {
+            let entry = FDT[fd];
+            if !entry.is_null() && (*entry).task == task {
+                if let Some(id) = (*entry).shm {
+                    shm_unref(id);
+                }
+                fd_free(fd as i32);
+            }
+        }
     }
 }
 
-pub fn file_close(fd: i32) -> i32 {
-    if fd < 0 || unsafe { FDT[fd as usize].stat.start } == 0 {
-        println!("fd {} does not exist.", fd);
-        return -1;
-    } else {
-        unsafe { FDT[fd as usize] = FdtEntry::null() };
-        return 0;
+/// Number of files opened by a task
+pub fn fd_count(task: i8) -> usize {
+    unsafe {
+        FDT.iter().filter(|&&entry| !entry.is_null() && (*entry).task == task).count()
     }
 }
 
+/// Programs are recognized by the magic number at the start of their header
 pub fn file_type(fd: i32) -> i32 {
-    let mut buf = [0;MAX_STR_LEN];
-    if file_read(fd, &mut buf[0], MAX_STR_LEN) != -1 {
+    if fd_is_valid(fd) && unsafe { (*FDT[fd as usize]).dev.is_some() } {
+        return TYPE_DEV;
+    }
+    let mut magic: u32 = 0;
+    if file_read(fd, &mut magic as *mut u32 as *mut u8, size_of::<u32>()).is_ok() {
         rewind(fd);
-        if bytes_to_str(&buf) != "\0" {
-            return TYPE_TEXT;
-        } else {
+        if magic == EXEC_MAGIC {
             return TYPE_EXEC;
+        } else {
+            return TYPE_TEXT;
         }
     }
     return -1;
 }
 
-pub fn set_superblock() {
-    unsafe { SB = Superblock::new(); }
+// Copies bytes between buf and the blocks of the file, following its FAT chain
+fn file_io_at(stat: &Stat, pos: usize, buf: *mut u8, n: usize, write: bool) -> usize {
+    unsafe {
+        if pos >= stat.size {
+            return 0;
+        }
+        let cnt = min(n, stat.size - pos);
+        let mut disk = IdeDisk;
+        // MAX_BLOCKS entries fit in the first sector of the FAT
+        let mut fat = [0;SECTOR_SIZE];
+        disk.read_sector(FAT_START_SECTOR, &mut fat);
+        
+        let mut block = stat.start;
+        for _ in 0..(pos / SB.block_size) {
+            block = fat[block] as usize;
+            if block == FAT_END as usize {
+                return 0;
+            }
+        }
+        let mut sector = [0;SECTOR_SIZE];
+        let mut done = 0;
+        while done < cnt {
+            let block_pos = (pos + done) % SB.block_size;
+            if block_pos == 0 && done != 0 {
+                block = fat[block] as usize;
+            }
+            if block == FAT_END as usize || block == FAT_FREE as usize {
+                break;
+            }
+            let sector_id = (block * SB.block_size + block_pos) / SECTOR_SIZE;
+            let offset = block_pos % SECTOR_SIZE;
+            let len = min(cnt - done, SECTOR_SIZE - offset);
+            if write {
+                // partial sectors must be read first to keep the rest of their content
+                if len != SECTOR_SIZE {
+                    disk.read_sector(sector_id as u32, &mut sector);
+                }
+                memcpy(&mut sector[offset], buf.offset(done as isize), len);
+                disk.write_sector(sector_id as u32, &sector);
+            } else {
+                disk.read_sector(sector_id as u32, &mut sector);
+                memcpy(buf.offset(done as isize), &sector[offset], len);
+            }
+            done += len;
+        }
+        return done;
+    }
 }
 
-fn free_fd() -> i32 {
+pub fn set_superblock() {
     unsafe {
-        let mut cnt = 0;
-        for entry in FDT.iter() {
-            if entry.stat.start == 0 {
-                return cnt;
+        SB = match Superblock::new() {
+            Some(sb) => sb,
+            None => {
+                println!("\nhda: not a MicroFS image\n");
+                return;
             }
-            cnt += 1;
+        };
+        let geometry = SB.geometry();
+        let replayed = journal_replay(&mut IdeDisk, &geometry);
+        if replayed != 0 {
+            println!("journal: {} sectors replayed", replayed);
+        }
+        let mut disk = IdeDisk;
+        let mut journal = Journal::begin(&mut disk, &geometry);
+        let report = fsck(&mut journal, &geometry, true);
+        // files added to the image since the last boot are created now
+        meta_stamp(&mut journal, &geometry, rtc_time());
+        let committed = journal.commit();
+        if !report.is_clean() {
+            println!("fsck: {} lost blocks, {} cross-links, {} bad chains, {} size mismatches",
+                report.lost_blocks, report.cross_links, report.bad_chains, report.size_mismatches);
+        }
+        match committed {
+            Ok(()) if report.repaired => println!("fsck: file system repaired\n"),
+            Ok(()) => {}
+            Err(_) => println!("fsck: the repair does not fit in the journal, disk left unchanged\n")
         }
-        return -1;
+    }
+}
+
+// Little-endian integers stored on the disk
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    unsafe { mem::transmute::<[u8;2], u16>([bytes[offset], bytes[offset+1]]) }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    unsafe {
+        mem::transmute::<[u8;4], u32>([bytes[offset], bytes[offset+1], bytes[offset+2], bytes[offset+3]])
+    }
+}
+
+// The descriptors of the other tasks are not valid for the running one
+fn fd_is_valid(fd: i32) -> bool {
+    fd >= 0 && (fd as usize) < FDT_SIZE &&
+        unsafe { !FDT[fd as usize].is_null() && (*FDT[fd as usize]).task == current_task() }
+}
+
+// Returns a free descriptor with a new entry from the fd cache
+fn fd_alloc() -> Result<i32, Error> {
+    unsafe {
+        let fd = match FDT.iter().position(|entry| entry.is_null()) {
+            Some(fd) => fd,
+            None => return Err(Error::TooManyFiles)
+        };
+        let entry = FD_CACHE.alloc() as *mut FdtEntry;
+        if entry.is_null() {
+            return Err(Error::NoMemory);
+        }
+        *entry = FdtEntry::null();
+        FDT[fd] = entry;
+        return Ok(fd as i32);
+    }
+}
+
+fn fd_free(fd: i32) {
+    unsafe {
+        FD_CACHE.free(FDT[fd as usize] as u32);
+        FDT[fd as usize] = null_mut();
     }
 }
 
 impl FdtEntry {
     const fn null() -> FdtEntry {
         FdtEntry {
-            stat: Stat {
-                name: [0;MAX_FILENAME_LENGTH],
-                size: 0,
-                entry_offset: 0,
-                start: 0
-            },
-            pos: 0
+            stat: Stat::null(),
+            pos: 0,
+            dev: None,
+            proc: None,
+            shm: None,
+            task: -1
         }
     }
 }
@@ -188,28 +478,49 @@ impl StatBuilder for Stat {
     fn new(filename: &str) -> Stat {
         let mut sector : [u16;SECTOR_SIZE/2] = [0;SECTOR_SIZE/2];
         let mut raw_filename = [0;MAX_FILENAME_LENGTH];
+        // device and proc files are not stored on the disk
+        let virtual_file = match (dev_lookup(filename), proc_lookup(filename)) {
+            (Some(dev), _) => Some((dev_size(dev), FileKind::Device, 0o666)),
+            (_, Some(proc)) => Some((proc_size(proc), FileKind::File, 0o444)),
+            _ => None
+        };
+        if let Some((size, kind, mode)) = virtual_file {
+            let len = if filename.len() < MAX_FILENAME_LENGTH { filename.len() } else { MAX_FILENAME_LENGTH };
+            raw_filename[0..len].copy_from_slice(&filename.as_bytes()[0..len]);
+            let mut stat = Stat::null();
+            stat.name = raw_filename;
+            stat.size = size;
+            stat.kind = kind;
+            stat.mode = mode;
+            stat.uid = ROOT_UID;
+            stat.ctime = boot_time();
+            stat.mtime = boot_time();
+            return stat;
+        }
         let mut it = FileIterator::new();
+        let root_sector = it.sector;
         while it.has_next() {
+            let entry_sector = it.sector;
+            let offset = it.offset;
             it.next(&mut raw_filename[0]);
-            if filename == bytes_to_str(&raw_filename) {
-                read_sector(it.sector, &mut sector[0] as *mut u16);
-                unsafe {
-                    let offset = it.offset - ENTRY_SIZE;
-                    let entries = mem::transmute::<[u16;SECTOR_SIZE/2], [u8;SECTOR_SIZE]>(sector);
-                    let start = [entries[offset+26], entries[offset+27]];
-                    let start = mem::transmute::<[u8;2], u16>(start);
-                    let size = [entries[offset+28], entries[offset+29], entries[offset+30], entries[offset+31]];
-                    let size = mem::transmute::<[u8;4], u32>(size);
-                    return Stat {
-                        name: raw_filename,
-                        size: size as usize,
-                        entry_offset: offset as u16,
-                        start: start as usize
-                    }
-                }
+            if bytes_to_str(&raw_filename) == Ok(filename) {
+                read_sector(entry_sector, &mut sector[0] as *mut u16);
+                let entries = unsafe {
+                    mem::transmute::<[u16;SECTOR_SIZE/2], [u8;SECTOR_SIZE]>(sector)
+                };
+                let entry = &entries[offset..offset+ENTRY_SIZE];
+                let mut stat = Stat::null();
+                stat.name = raw_filename;
+                stat.size = read_u32(entry, ENTRY_SIZE_OFFSET) as usize;
+                stat.entry_offset = ((entry_sector - root_sector) as usize * SECTOR_SIZE + offset) as u16;
+                stat.start = read_u16(entry, ENTRY_START_OFFSET) as usize;
+                meta_read(&mut IdeDisk, unsafe { &SB.geometry() }, &mut stat);
+                return stat;
             }
         }
-        Stat { name: raw_filename, size: 0, entry_offset: 0, start: 0 }
+        let mut stat = Stat::null();
+        stat.name = raw_filename;
+        return stat;
     }
 }
 
@@ -255,30 +566,56 @@ impl FileIteratorBuilder for FileIterator {
 
 impl Superblock {
     const fn null() -> Superblock {
-        Superblock { block_size: 0, fat_size: 0, root_entry: 0 }
+        Superblock {
+            block_size: 0, fat_size: 0, root_entry: 0,
+            journal_start: 0, journal_sectors: 0, meta_start: 0, meta_sectors: 0
+        }
     }
     
-    fn new() -> Superblock {
+    fn new() -> Option<Superblock> {
         let mut sector : [u16;SECTOR_SIZE/2] = [0;SECTOR_SIZE/2];
         read_sector(0, &mut sector[0] as *mut u16);
         let raw_sb = unsafe {
             mem::transmute::<[u16;SECTOR_SIZE/2], [u8;SECTOR_SIZE]>(sector)
         };
-        let label = bytes_to_str(&raw_sb[0x52..0x59]);
-        let block_size = raw_sb[13] as usize * SECTOR_SIZE;
-        let fat_size = unsafe {
-            mem::transmute::<[u8;4], u32>([raw_sb[0x24], raw_sb[0x25], raw_sb[0x26], raw_sb[0x27]])
-        };
-        let root_entry = raw_sb[0x2c];
+        let label = bytes_to_str(&raw_sb[0x52..0x59]).unwrap_or("MicroFS");
+        let geometry = FsGeometry::from_superblock(&raw_sb)?;
         println!("\n{} ready.", label);
-        println!("Block size = {} bytes", block_size);
-        println!("FAT size = {} bytes", fat_size);
-        println!("Root entry = block number {}\n", root_entry);
+        println!("Block size = {} bytes", geometry.block_size);
+        println!("FAT size = {} bytes", geometry.fat_size);
+        println!("Root entry = block number {}", geometry.root_entry);
+        if geometry.has_journal() {
+            println!("Journal = {} sectors at sector {}", geometry.journal_sectors, geometry.journal_start);
+        } else {
+            println!("No journal");
+        }
+        if geometry.has_meta() {
+            println!("Metadata table = {} sectors at sector {}\n", geometry.meta_sectors, geometry.meta_start);
+        } else {
+            println!("No metadata table, files get default permissions\n");
+        }
         
-        Superblock {
-            block_size: block_size,
-            fat_size: fat_size as usize,
-            root_entry: root_entry as usize
+        Some(Superblock {
+            block_size: geometry.block_size,
+            fat_size: geometry.fat_size,
+            root_entry: geometry.root_entry,
+            journal_start: geometry.journal_start,
+            journal_sectors: geometry.journal_sectors,
+            meta_start: geometry.meta_start,
+            meta_sectors: geometry.meta_sectors
+        })
+    }
+    
+    /// Metadata updates must go through a Journal built with this geometry
+    pub fn geometry(&self) -> FsGeometry {
+        FsGeometry {
+            block_size: self.block_size,
+            fat_size: self.fat_size,
+            root_entry: self.root_entry,
+            journal_start: self.journal_start,
+            journal_sectors: self.journal_sectors,
+            meta_start: self.meta_start,
+            meta_sectors: self.meta_sectors
         }
     }
 }
\ No newline at end of file
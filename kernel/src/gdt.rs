@@ -6,8 +6,8 @@ use core::mem::size_of;
 use x86::*;
 use task::*;
 
-/// The GDT size (not including the tss and ldt entries)
-pub const GDT_SIZE: usize = 6;
+/// The GDT size (not including the tss and ldt entries of the tasks)
+pub const GDT_SIZE: usize = 7;
 
 /// Converts a descriptor index in the GDT into a selector
 pub const fn gdt_index_to_selector(idx: u32) -> u32 {idx << 3}
@@ -9,13 +9,40 @@
 * ATA disk1, I/O ports: 0x170-0x177, 0x376
 */
 
+use core::mem;
+use core::mem::size_of;
+use rlibc::memcpy;
 use pio::*;
+use slab::SlabCache;
+use common::BlockDevice;
 
 // IDE ports
 const IDE_CMD : u16 = 0x1f7;
 const IDE_DATA : u16 = 0x1f0;
+const IDE_DRIVE : u16 = 0x1f6;
 
-pub const SECTOR_SIZE : usize = 512;
+// Drives of the primary bus
+pub const IDE_MASTER: u8 = 0;
+pub const IDE_SLAVE: u8 = 1;
+
+pub use common::SECTOR_SIZE;
+
+// Sectors of the first disk kept in memory, the least recently used one is replaced
+const BCACHE_SIZE: usize = 32;
+
+static mut BLOCK_CACHE: SlabCache = SlabCache::new("block-cache", size_of::<CachedSector>());
+static mut CACHED_SECTORS: [*mut CachedSector;BCACHE_SIZE] = [0 as *mut CachedSector;BCACHE_SIZE];
+static mut BCACHE_CLOCK: u32 = 0;
+
+/// The first disk seen as a block device
+pub struct IdeDisk;
+
+#[repr(C)]
+struct CachedSector {
+    sector: u32,
+    last_use: u32,
+    data: [u16;SECTOR_SIZE/2]
+}
 
 /**
  * Wait for the disk drive to be ready.
@@ -26,28 +53,83 @@ fn wait_drive() {
 
 /**
  * Prepare the disk drive for read/write at the specified sector in LBA mode.
+ * @param drive IDE_MASTER or IDE_SLAVE
  * @param sector the sector to read or write (0-indexed).
  */
-fn pio_prepare(sector: u32) {
+fn pio_prepare(drive: u8, sector: u32) {
     unsafe {
     	wait_drive();
     	outb(0x1f2, 1);                                        // 1 sector
     	outb(0x1f3, (sector & 0xff) as u8);                    // send bits 0-7 of LBA
     	outb(0x1f4, ((sector >> 8) & 0xff) as u8);             // send bits 8-15 of LBA
     	outb(0x1f5, ((sector >> 16) & 0xff) as u8);            // send bits 16-23 of LBA
-    	outb(0x1f6, ((sector >> 24) & 0x0f) as u8 | 0xe0);     // send bits 24-27 of LBA + set LBA mode; 0xe0 = 11100000b;
+    	outb(IDE_DRIVE, ((sector >> 24) & 0x0f) as u8 | 0xe0 | drive << 4);  // send bits 24-27 of LBA + set LBA mode; 0xe0 = 11100000b;
+    }
+}
+
+/**
+ * Identify a drive of the primary bus.
+ * @param drive IDE_MASTER or IDE_SLAVE
+ * @return the number of sectors addressable in LBA mode, None if there is no drive
+ */
+pub fn ide_identify(drive: u8) -> Option<u32> {
+    unsafe {
+        outb(IDE_DRIVE, 0xa0 | drive << 4);
+        for port in 0x1f2..0x1f6 {
+            outb(port, 0);
+        }
+        outb(IDE_CMD, 0xec);  // identify
+        if inb(IDE_CMD) == 0 || inb(IDE_CMD) == 0xff {
+            return None;
+        }
+        while inb(IDE_CMD) & 0x80 != 0 { }
+        loop {
+            let status = inb(IDE_CMD);
+            if status & 0x1 != 0 {
+                return None;
+            }
+            if status & 0x8 != 0 {
+                break;
+            }
+        }
+        let mut data = [0;SECTOR_SIZE/2];
+        for i in 0..(SECTOR_SIZE/2) {
+            data[i] = inw(IDE_DATA);
+        }
+        // words 60 and 61 hold the number of LBA28 sectors
+        Some(data[60] as u32 | (data[61] as u32) << 16)
     }
 }
 
 /**
- * Read sectors from the first disk.
+ * Read sectors from the first disk, through the block cache.
  * @param sector first sector to read (0-indexed)
  * @param dst address to store to read data
  * Based on the assembly code at http://wiki.osdev.org/ATA_read/write_sectors
  */
 pub fn read_sector(sector: u32, dst: *mut u16) {
     unsafe {
-    	pio_prepare(sector);
+        match bcache_lookup(sector) {
+            Some(cached) => {
+                memcpy(dst as *mut u8, &(*cached).data[0] as *const u16 as *const u8, SECTOR_SIZE);
+            }
+            None => {
+                read_sector_from(IDE_MASTER, sector, dst);
+                bcache_store(sector, dst);
+            }
+        }
+    }
+}
+
+/**
+ * Read sectors from a disk of the primary bus.
+ * @param drive IDE_MASTER or IDE_SLAVE
+ * @param sector first sector to read (0-indexed)
+ * @param dst address to store to read data
+ */
+pub fn read_sector_from(drive: u8, sector: u32, dst: *mut u16) {
+    unsafe {
+    	pio_prepare(drive, sector);
 
     	outb(IDE_CMD, 0x20);  // read with retry
     	wait_drive();
@@ -59,13 +141,24 @@ pub fn read_sector(sector: u32, dst: *mut u16) {
 }
 
 /**
- * Write sectors from the first disk.
+ * Write sectors from the first disk. The block cache is written through.
  * @param sector first sector to write (0-indexed)
  * @param src address of the data to be written
  */
 pub fn write_sector(sector: u32, src: *mut u16) {
+    write_sector_to(IDE_MASTER, sector, src);
+    unsafe { bcache_store(sector, src); }
+}
+
+/**
+ * Write sectors to a disk of the primary bus.
+ * @param drive IDE_MASTER or IDE_SLAVE
+ * @param sector first sector to write (0-indexed)
+ * @param src address of the data to be written
+ */
+pub fn write_sector_to(drive: u8, sector: u32, src: *mut u16) {
     unsafe {
-    	pio_prepare(sector);
+    	pio_prepare(drive, sector);
 
     	outb(IDE_CMD, 0x30);  // write with retry
     	wait_drive();
@@ -74,4 +167,61 @@ pub fn write_sector(sector: u32, src: *mut u16) {
             outw(IDE_DATA, *src.offset(i as isize));
         }
     }
+}
+
+impl BlockDevice for IdeDisk {
+    fn read_sector(&mut self, sector: u32, buf: &mut [u8;SECTOR_SIZE]) {
+        let mut data : [u16;SECTOR_SIZE/2] = [0;SECTOR_SIZE/2];
+        read_sector(sector, &mut data[0] as *mut u16);
+        *buf = unsafe { mem::transmute::<[u16;SECTOR_SIZE/2], [u8;SECTOR_SIZE]>(data) };
+    }
+    
+    fn write_sector(&mut self, sector: u32, buf: &[u8;SECTOR_SIZE]) {
+        let mut data = unsafe { mem::transmute::<[u8;SECTOR_SIZE], [u16;SECTOR_SIZE/2]>(*buf) };
+        write_sector(sector, &mut data[0] as *mut u16);
+    }
+}
+
+// Returns the cached copy of sector, None if it is not in the cache
+unsafe fn bcache_lookup(sector: u32) -> Option<*mut CachedSector> {
+    for &cached in CACHED_SECTORS.iter() {
+        if !cached.is_null() && (*cached).sector == sector {
+            BCACHE_CLOCK += 1;
+            (*cached).last_use = BCACHE_CLOCK;
+            return Some(cached);
+        }
+    }
+    return None;
+}
+
+// Copies the content of sector in the cache, in place of the least recently
+// used sector if the cache is full. Nothing is cached if the heap is full.
+unsafe fn bcache_store(sector: u32, src: *const u16) {
+    let cached = match bcache_lookup(sector) {
+        Some(cached) => cached,
+        None => {
+            let mut slot = 0;
+            for i in 0..BCACHE_SIZE {
+                if CACHED_SECTORS[i].is_null() {
+                    slot = i;
+                    break;
+                }
+                if (*CACHED_SECTORS[i]).last_use < (*CACHED_SECTORS[slot]).last_use {
+                    slot = i;
+                }
+            }
+            if CACHED_SECTORS[slot].is_null() {
+                CACHED_SECTORS[slot] = BLOCK_CACHE.alloc() as *mut CachedSector;
+                if CACHED_SECTORS[slot].is_null() {
+                    return;
+                }
+            }
+            let cached = CACHED_SECTORS[slot];
+            (*cached).sector = sector;
+            BCACHE_CLOCK += 1;
+            (*cached).last_use = BCACHE_CLOCK;
+            cached
+        }
+    };
+    memcpy(&mut (*cached).data[0] as *mut u16 as *mut u8, src as *const u8, SECTOR_SIZE);
 }
\ No newline at end of file
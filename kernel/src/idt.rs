@@ -5,11 +5,15 @@ use core::mem::size_of;
 use x86::*;
 use vga::*;
 use pic::*;
+use paging::get_cr2;
+use vm::vm_fault;
+use task::*;
 use timer::timer_handler;
 use keyboard::keyboard_handler;
 use syscall::_syscall_handler;
 
 const IDT_SIZE: usize = 256;
+pub const IRQ_NB: usize = 16;
 const EXCEPTION_MESSAGES: [&str;21] = [
 	"EXCEPTION 0 : Divide error",
 	"EXCEPTION 1 : Intel RESERVED exception number",
@@ -36,6 +40,7 @@ const EXCEPTION_MESSAGES: [&str;21] = [
 
 static mut IDT: Idt = [IdtEntry::null();IDT_SIZE];
 static mut IDT_PTR: IdtPtr = IdtPtr::null();
+static mut IRQ_COUNTS: [u32;IRQ_NB] = [0;IRQ_NB];
 
 type Idt = [IdtEntry; IDT_SIZE];
 
@@ -85,7 +90,9 @@ pub fn idt_init() {
         IDT[5] = IdtEntry::new(GDT_KERNEL_CODE_SELECTOR as u16, _exception_5 as *const () as u32, TYPE_INTERRUPT_GATE, DPL_KERNEL);
         IDT[6] = IdtEntry::new(GDT_KERNEL_CODE_SELECTOR as u16, _exception_6 as *const () as u32, TYPE_INTERRUPT_GATE, DPL_KERNEL);
         IDT[7] = IdtEntry::new(GDT_KERNEL_CODE_SELECTOR as u16, _exception_7 as *const () as u32, TYPE_INTERRUPT_GATE, DPL_KERNEL);
-        IDT[8] = IdtEntry::new(GDT_KERNEL_CODE_SELECTOR as u16, _exception_8 as *const () as u32, TYPE_INTERRUPT_GATE, DPL_KERNEL);
+        // double faults switch to a task of their own, see double_fault_handler
+        let double_fault_tss = double_fault_task_init(double_fault_handler as *const () as u32);
+        IDT[8] = IdtEntry::new(double_fault_tss, 0, TYPE_TASK_GATE, DPL_KERNEL);
         IDT[9] = IdtEntry::new(GDT_KERNEL_CODE_SELECTOR as u16, _exception_9 as *const () as u32, TYPE_INTERRUPT_GATE, DPL_KERNEL);
         IDT[10] = IdtEntry::new(GDT_KERNEL_CODE_SELECTOR as u16, _exception_10 as *const () as u32, TYPE_INTERRUPT_GATE, DPL_KERNEL);
         IDT[11] = IdtEntry::new(GDT_KERNEL_CODE_SELECTOR as u16, _exception_11 as *const () as u32, TYPE_INTERRUPT_GATE, DPL_KERNEL);
@@ -132,7 +139,33 @@ pub fn idt_init() {
 #[no_mangle]
 pub extern fn exception_handler(regs: *mut Regs) {
     unsafe {
-        panic!(EXCEPTION_MESSAGES[(*regs).number as usize]);
+        let number = (*regs).number;
+        if number == 14 && vm_fault(get_cr2(), (*regs).error_code) {
+            return;
+        }
+        // faulty user tasks are killed, the kernel must not fault
+        if (*regs).cs & 0x3 == DPL_USER as u32 && current_task() != -1 {
+            let eip = (*regs).eip;
+            if number == 14 {
+                println!("{} at {:#x} (address {:#x}), task killed", EXCEPTION_MESSAGES[number as usize], eip, get_cr2());
+            } else {
+                println!("{} at {:#x}, task killed", EXCEPTION_MESSAGES[number as usize], eip);
+            }
+            task_abort();
+        }
+        panic!(EXCEPTION_MESSAGES[number as usize]);
+    }
+}
+
+/// Entry point of the double fault task. An overflow of a kernel stack faults
+/// on its guard page, then again when the cpu pushes the state of the page
+/// fault on the same stack, which raises a double fault. This task has its
+/// own stack to report it.
+pub extern fn double_fault_handler() -> ! {
+    let addr = unsafe { get_cr2() };
+    match stack_guard_task(addr) {
+        Some(task) => panic!("kernel stack overflow of task {} (address {:#x})", task, addr),
+        None => panic!(EXCEPTION_MESSAGES[8])
     }
 }
 
@@ -141,6 +174,9 @@ pub extern fn exception_handler(regs: *mut Regs) {
 #[no_mangle]
 pub extern fn irq_handler(regs: *mut Regs) {
     let irq = unsafe { (*regs).number };
+    if (irq as usize) < IRQ_NB {
+        unsafe { IRQ_COUNTS[irq as usize] += 1; }
+    }
     match irq {
         0 => timer_handler(),
         1 => keyboard_handler(),
@@ -150,6 +186,11 @@ pub extern fn irq_handler(regs: *mut Regs) {
     pic_eoi(irq);
 }
 
+/// Number of times the given irq has been raised since boot
+pub fn irq_count(irq: usize) -> u32 {
+    unsafe { IRQ_COUNTS[irq] }
+}
+
 impl IdtEntry {
     const fn null() -> IdtEntry {
         IdtEntry {
@@ -2,18 +2,22 @@
 //!
 //! `rust_os` is a kernel running on IA-32 architecture
 
-#![feature(lang_items, asm, const_fn)]
+#![feature(lang_items, asm, const_fn, alloc, allocator_api, global_allocator)]
 #![no_std]
 
 extern crate rlibc;
 extern crate common;
+extern crate alloc;
 
 pub mod x86;
 pub mod multiboot;
 pub mod vga;
 pub mod pio;
 pub mod paging;
+pub mod frame;
 pub mod kheap;
+pub mod varea;
+pub mod slab;
 pub mod gdt;
 pub mod pic;
 pub mod idt;
@@ -21,7 +25,15 @@ pub mod timer;
 pub mod keyboard;
 pub mod ide;
 pub mod fs;
+pub mod dev;
+pub mod proc;
+pub mod rtc;
 pub mod task;
+pub mod vm;
+pub mod shm;
+pub mod swap;
+pub mod uaccess;
+pub mod trace;
 pub mod syscall;
 
 use x86::*;
@@ -29,13 +41,18 @@ use multiboot::*;
 use vga::*;
 use pio::disable_cursor;
 use paging::*;
+use frame::*;
 use kheap::*;
 use gdt::gdt_init; 
 use pic::pic_init;
 use idt::idt_init;
 use timer::*;
 use fs::*;
+use rtc::rtc_init;
 use task::*;
+use dev::dev_init;
+use swap::swap_init;
+use syscall::sysenter_init;
 use common::*;
 
 // exports
@@ -46,6 +63,10 @@ pub use syscall::syscall_handler;
 #[cfg(test)]
 mod test;
 
+#[cfg(not(test))]
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator;
+
 /// Entrypoint to the rust code. This function is called by the bootstrap code
 /// contain in bootstrap_asm.s
 #[no_mangle]
@@ -55,7 +76,12 @@ pub extern fn kmain(_multiboot_magic: u32, multiboot_info: *mut MultibootInfo) {
     println!("Screen initialized.");
     paging_init();
     println!("Paging initialized.");
-    kheap_init(mboot.mem_upper);
+    if nx_enabled() {
+        println!("NX bit enabled.");
+    }
+    frame_init(&mboot, phys!(multiboot_info as u32));
+    println!("Frame allocator initialized.");
+    kheap_init();
     println!("Heap initialized.");
     gdt_init();
     println!("GDT initialized.");
@@ -63,17 +89,27 @@ pub extern fn kmain(_multiboot_magic: u32, multiboot_info: *mut MultibootInfo) {
     println!("PIC initialized.");
     idt_init();
     println!("IDT initialized.");
+    if sysenter_init() {
+        println!("Sysenter initialized.");
+    }
     sti();
     println!("Interrupts unmasked.");
     timer_init(50);
     println!("PIT initialized.");
+    rtc_init();
+    println!("RTC initialized.");
+    dev_init();
     set_superblock();
+    swap_init();
     println!("Welcome to RustOS!");
-    println!("Available Memory = {} kB", mboot.mem_upper);
+    println!("Available Memory = {} kB", frame_stats().total * FRAME_SIZE / 1024);
     sleep(3000);
-    exec("splash");
-    exec("shell");
-    print_kmalloc_list();
+    for program in ["splash", "shell"].iter() {
+        if let Err(err) = exec(program, false) {
+            println!("exec: {}: {}", program, err);
+        }
+    }
+    kheap_report();
     disable_cursor();
     print!("\nKernel stopped.\nYou can turn off you computer.");
 }
@@ -89,6 +125,13 @@ pub extern fn panic_fmt(details: ::core::fmt::Arguments, file: &'static str, lin
     }
 }
 
+#[cfg(not(test))]
+#[lang = "oom"]
+#[no_mangle]
+pub extern fn rust_oom(layout: ::core::alloc::Layout) -> ! {
+    panic!("out of memory: {} bytes requested", layout.size());
+}
+
 #[no_mangle]
 pub extern "C" fn __floatundisf() {
     loop {
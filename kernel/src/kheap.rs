@@ -1,21 +1,65 @@
 #![allow(dead_code)]
 
 use core::mem::size_of;
+use core::cmp::min;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
 use rlibc::{memset,memcpy};
 use paging::*;
+use frame::*;
+use slab::*;
+use varea::AREA_ORDER;
+use swap::swap_frame_alloc;
 use vga::*;
+use x86::{InterruptGuard, return_address};
+use common::USER_FRAMES_START;
+
+// the heap must stay below the last page table of the kernel
+const KHEAP_MAX_END: u32 = 0xFFC00000;
+const KHEAP_MAGIC: u32 = 0x4ea9b10c;
+// kmalloc addresses are aligned on 16 bytes
+const KMALLOC_ALIGN: usize = 16;
+
+// With the kheap_debug feature every allocation takes a heap block, tagged with
+// the address of its caller and followed by a redzone. Freed blocks are poisoned.
+pub const KHEAP_DEBUG: bool = cfg!(feature = "kheap_debug");
+const REDZONE_SIZE: usize = 16;
+const REDZONE_BYTE: u8 = 0xfd;
+const POISON_BYTE: u8 = 0x6b;
+const MAX_LEAK_SITES: usize = 32;
 
 pub static mut KHEAP_SIZE: usize = 0x1000000;
 pub static mut KHEAP_ADDR: u32 = 0;
 pub static mut KHEAP_END: u32 = 0;
 
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub total: usize,
+    pub used: usize,
+    pub free: usize,
+    pub blocks: usize
+}
+
+/// Allocator of the alloc crate collections, built on kmalloc and kfree
+pub struct KernelAllocator;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C, align(16))]
 pub struct Header {
     previous: u32,
     next: u32,
     size: usize,
-    free: bool
+    free: bool,
+    magic: u32,
+    caller: u32,        // address of the call to kmalloc, debug mode only
+    requested: usize    // size asked by the caller, debug mode only
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LeakSite {
+    caller: u32,
+    blocks: usize,
+    bytes: usize
 }
 
 macro_rules! align {
@@ -28,28 +72,66 @@ macro_rules! align {
     }
 }
 
-pub fn kheap_init(ram_size: u32) {
+/// The heap is mapped at phys + KERNEL_BASE right after the kernel. Its frames
+/// are reserved in the frame allocator, about half of the free memory, so that
+/// the rest can be given to the user tasks. The low memory, the kernel and the
+/// heap are mapped once with large pages, so the heap is extended to the end of
+/// its last large page and the frames of this page that it cannot take are
+/// never handed out. When the free frames end before this page, because of a
+/// hole or a reserved region, the end of the heap is mapped with 4 KiB pages
+/// so that no memory past the free frames is mapped.
+pub fn kheap_init() {
     unsafe {
-        KHEAP_ADDR = get_kernel_end();
-        KHEAP_SIZE = ((ram_size / 1000 - 1) * 0x100000 - phys!(KHEAP_ADDR)) as usize;
-        KHEAP_END = KHEAP_ADDR + KHEAP_SIZE as u32;
-        if (KHEAP_ADDR & 0xfffff000) != KHEAP_ADDR {
-            KHEAP_ADDR &= 0xfffff000;
-            KHEAP_ADDR += 0x1000;
+        let start = align!(phys!(get_kernel_end()));
+        let free = frame_free_area(start as PhysAddr, phys!(KHEAP_MAX_END) as u64);
+        let frames = min(free, frame_stats().free / 2);
+        let free_end = start + (free * FRAME_SIZE) as u32;
+        let end = min(table_align(start + (frames * FRAME_SIZE) as u32), free_end);
+        KHEAP_ADDR = virt!(start);
+        KHEAP_SIZE = (end - start) as usize;
+        KHEAP_END = virt!(end);
+        if table_align(end) <= free_end {
+            frame_reserve_area(start as PhysAddr, table_align(end) as PhysAddr);
+            INITIAL_PD.map_kernel_area(KERNEL_BASE, virt!(table_align(end)));
+        } else {
+            let tail = end &! (TABLE_SIZE as u32 - 1);
+            frame_reserve_area(start as PhysAddr, end as PhysAddr);
+            INITIAL_PD.map_kernel_area(KERNEL_BASE, virt!(tail));
+            INITIAL_PD.map_kernel_tail(virt!(tail), virt!(end));
         }
-        let mut entry_addr = 0;
-        INITIAL_PD.alloc_frame(&mut entry_addr, &mut phys!(KHEAP_ADDR), KERNEL_MODE);
-        memset(entry_addr as *mut u8, 0, FRAME_SIZE);
-        memcpy(entry_addr as *mut u8, Header::null(0, KHEAP_SIZE).as_ptr(), size_of::<Header>());
+        memset(KHEAP_ADDR as *mut u8, 0, FRAME_SIZE);
+        memcpy(KHEAP_ADDR as *mut u8, Header::null(0, KHEAP_SIZE).as_ptr(), size_of::<Header>());
     }
 }
 
+/// Returns the address of size zeroed bytes, 0 if the heap is full.
+/// Small sizes are served by the slab caches, the others take whole pages.
+/// Never inlined, so that the caller recorded in the block is the one of its frame.
+#[inline(never)]
 pub fn kmalloc(size: usize) -> u32 {
-    let aligned_size = align!(size) + align!(size_of::<Header>()) - size_of::<Header>();
-    let mut addr = empty_block(aligned_size);
-    if kmalloc_table_check(addr, aligned_size) {
-        addr = empty_block(aligned_size);
+    kmalloc_from(size, return_address(0))
+}
+
+/// Returns the address of size zeroed bytes aligned on a page, 0 if the heap is full.
+/// The block starts at the end of the previous page with its header.
+#[inline(never)]
+pub fn kmalloc_page(size: usize) -> u32 {
+    // the redzone must follow the page and not the header
+    let size = if KHEAP_DEBUG { size + FRAME_SIZE - size_of::<Header>() } else { size };
+    let addr = kmalloc_from(size, return_address(0));
+    if addr == 0 {
+        return 0;
+    }
+    return addr + (FRAME_SIZE - size_of::<Header>()) as u32;
+}
+
+fn kmalloc_from(size: usize, caller: u32) -> u32 {
+    if size <= SLAB_MAX_SIZE && !KHEAP_DEBUG {
+        return slab_alloc(size);
     }
+    let block_size = if KHEAP_DEBUG { size + REDZONE_SIZE } else { size };
+    let aligned_size = align!(block_size) + align!(size_of::<Header>()) - size_of::<Header>();
+    let mut addr = empty_block(aligned_size);
     let mut block = Header::from_ptr(addr as *mut u8);
     if block.size >= aligned_size && block.free {
         if block.next == 0 {
@@ -58,85 +140,189 @@ pub fn kmalloc(size: usize) -> u32 {
             block.insert(addr, aligned_size);
         }
         addr += size_of::<Header>() as u32;
-        unsafe { memset(addr as *mut u8, 0, aligned_size); }
+        unsafe {
+            memset(addr as *mut u8, 0, aligned_size);
+            if KHEAP_DEBUG {
+                let header = Header::from_ptr(block_addr(addr) as *const u8);
+                header.tag(block_addr(addr), caller, size);
+                memset((addr + size as u32) as *mut u8, REDZONE_BYTE as i32, REDZONE_SIZE);
+            }
+        }
         return addr;
     }
     return 0;
 }
 
+/// Frees an address returned by kmalloc_page
+pub fn kfree_page(addr: u32) {
+    kfree(addr - (FRAME_SIZE - size_of::<Header>()) as u32);
+}
+
 pub fn kfree(addr: u32) {
-    let header_addr = addr - size_of::<Header>() as u32;
+    if is_slab_object(addr) {
+        slab_free(addr);
+        return;
+    }
+    if !is_heap_address(addr) {
+        println!("kfree: {:#x} is not a heap address", addr);
+        return;
+    }
+    let header_addr = block_addr(addr);
     let mut header = Header::from_ptr(header_addr as *const u8);
-    if !header.free {
-        let mut start_idx = header_addr as usize / FRAME_SIZE + 1;
-        let mut end_idx = (header_addr as usize + header.size) / FRAME_SIZE + 1;
-        if header.previous != 0 {
-            let previous = Header::from_ptr(header.previous as *const u8);
-            if previous.free {
-                start_idx -= 1;
-            }
+    if header.magic != KHEAP_MAGIC {
+        println!("kfree: {:#x}: corrupted header", addr);
+        return;
+    }
+    if header.free {
+        println!("kfree: {:#x}: double free", addr);
+        return;
+    }
+    if KHEAP_DEBUG {
+        if !header.redzone_is_intact(addr) {
+            println!("kfree: {:#x}: redzone overwritten, allocated from {:#x}", addr, header.caller);
         }
-        if header.next != 0 {
-            let next = Header::from_ptr(header.next as *const u8);
-            if next.free {
-                end_idx += 1;
-            }
+        unsafe { memset(addr as *mut u8, POISON_BYTE as i32, header.size); }
+    }
+    header.remove(header_addr);
+}
+
+/// Maps size bytes of free frames in the user directory, between
+/// USER_FRAMES_START and USER_HEAP_START. Returns the virtual address of the
+/// area or None if the memory or the area is full.
+pub fn umalloc(size: usize) -> Option<u32> {
+    unsafe {
+        let frames = align!(size) / FRAME_SIZE;
+        let order = frame_order(frames);
+        if frames == 0 || (*USER_PD).area.is_null() {
+            return None;
         }
-        header.remove(header_addr);
-        unsafe {
-            // free frames
-            for i in start_idx..end_idx {
-                INITIAL_PD.free_frame(i);
+        let virt_addr = match (*(*USER_PD).area).alloc(order) {
+            Some(page) => USER_FRAMES_START + (page * FRAME_SIZE) as u32,
+            None => {
+                println!("umalloc: no free area of {} pages", frames);
+                return None;
             }
-            // free tables
-            for i in (start_idx / TABLE_FSIZE)..(end_idx / TABLE_FSIZE + 1) {
-                if INITIAL_PD.table_is_empty(i) {
-                    let mut table_addr = virt!(INITIAL_PD[i] &! 0xfff);
-                    table_addr -= FRAME_SIZE as u32;
-                    let mut table_header = Header::from_ptr(table_addr as *const u8);
-                    table_header.remove(table_addr);
-                    INITIAL_PD[i] = 0;
-                }
+        };
+        if !umalloc_table_check(virt_addr, frames) {
+            println!("umalloc: out of memory");
+            (*(*USER_PD).area).free(page_index(virt_addr), order);
+            return None;
+        }
+        
+        // physically contiguous frames when possible
+        let block = frame_alloc_exact(frames);
+        for i in 0..frames {
+            let phys_addr = if block != 0 { block + (i * FRAME_SIZE) as PhysAddr } else { swap_frame_alloc() };
+            if phys_addr == 0 {
+                println!("umalloc: out of memory");
+                unmap_frames(virt_addr, i);
+                (*(*USER_PD).area).free(page_index(virt_addr), order);
+                return None;
             }
+            let addr = virt_addr + (i * FRAME_SIZE) as u32;
+            (*USER_PD).map_page(addr, phys_addr, USER_MODE);
+            memset(addr as *mut u8, 0, FRAME_SIZE);
         }
+        return Some(virt_addr);
     }
 }
 
-pub fn umalloc(size: usize) -> u32 {
+/// Unmaps the size bytes given by umalloc at addr from the user directory and
+/// gives their frames back to the frame allocator
+pub fn ufree(addr: u32, size: usize) {
+    let frames = align!(size) / FRAME_SIZE;
     unsafe {
-        let aligned_size = align!(size);
-        
-        let pd_backup = if get_cr3() != phys!(INITIAL_PD.tables as u32) {
-            switch_directory(&mut INITIAL_PD);
-            USER_PD
-        } else {
-            &mut INITIAL_PD as *mut PageDirectory
-        };
-        umalloc_table_check(aligned_size);
-        let mut phys_addr = phys!(kmalloc(size) + (FRAME_SIZE - size_of::<Header>()) as u32);
-        switch_directory(pd_backup);
-        
-        let mut virt_addr = 0;
-        let mut tmp = 0;
-        (*USER_PD).alloc_frame(&mut virt_addr, &mut phys_addr, USER_MODE);
-        for i in 1..(aligned_size / FRAME_SIZE) {
-            (*USER_PD).alloc_frame(&mut tmp, &mut (phys_addr + (i * FRAME_SIZE) as u32), USER_MODE);
+        unmap_frames(addr, frames);
+        if addr >= USER_FRAMES_START && page_index(addr) < 1 << AREA_ORDER && !(*USER_PD).area.is_null() {
+            (*(*USER_PD).area).free(page_index(addr), frame_order(frames));
         }
-        return virt_addr;
     }
 }
 
-pub fn ufree(addr: u32) {
-    unsafe {
-        let frame_idx = addr / FRAME_SIZE as u32;
-        let table_idx = frame_idx as usize / TABLE_FSIZE;
-        let entry_idx = frame_idx as usize % TABLE_FSIZE;
-        let table_ptr = virt!((*USER_PD)[table_idx] &! 0xfff) as *mut PageTable;
-        let entry_addr = virt!((*table_ptr)[entry_idx] &! 0xfff);
-        kfree(entry_addr - (FRAME_SIZE - size_of::<Header>()) as u32);
+// Unmaps the frames pages starting at addr and frees their frames
+unsafe fn unmap_frames(addr: u32, frames: usize) {
+    for i in 0..frames {
+        let phys_addr = (*USER_PD).unmap_page(addr + (i * FRAME_SIZE) as u32);
+        if phys_addr != 0 {
+            frame_free(phys_addr);
+        }
     }
 }
 
+// Index of the page at addr in the area of umalloc
+fn page_index(addr: u32) -> usize {
+    (addr - USER_FRAMES_START) as usize / FRAME_SIZE
+}
+
+/// Walks the heap and checks the magic and the links of each block, as well as
+/// the redzones in debug mode. Returns the number of corrupted blocks.
+pub fn kheap_check() -> usize {
+    let mut errors = 0;
+    let mut previous = 0;
+    let mut addr = unsafe { KHEAP_ADDR };
+    while addr != 0 {
+        let block = Header::from_ptr(addr as *const u8);
+        if block.magic != KHEAP_MAGIC || block.previous != previous {
+            // the rest of the list cannot be trusted
+            println!("kheap: {:#x}: corrupted header", addr);
+            return errors + 1;
+        }
+        if !block.free && KHEAP_DEBUG && !block.redzone_is_intact(addr + size_of::<Header>() as u32) {
+            println!("kheap: {:#x}: redzone overwritten, allocated from {:#x}", addr + size_of::<Header>() as u32, block.caller);
+            errors += 1;
+        }
+        previous = addr;
+        addr = block.next;
+    }
+    return errors;
+}
+
+/// Checks the heap and prints the blocks still in use, grouped by the address
+/// of the call to kmalloc in debug mode
+pub fn kheap_report() {
+    let errors = kheap_check();
+    if errors != 0 {
+        println!("kheap: {} corrupted blocks", errors);
+    }
+    if !KHEAP_DEBUG {
+        print_kmalloc_list();
+        return;
+    }
+    let mut sites = [LeakSite { caller: 0, blocks: 0, bytes: 0 };MAX_LEAK_SITES];
+    let mut others = LeakSite { caller: 0, blocks: 0, bytes: 0 };
+    let mut addr = unsafe { KHEAP_ADDR };
+    while addr != 0 {
+        let block = Header::from_ptr(addr as *const u8);
+        if !block.free {
+            let idx = sites.iter().position(|site| site.blocks == 0 || site.caller == block.caller);
+            let site = match idx {
+                Some(idx) => &mut sites[idx],
+                None => &mut others
+            };
+            site.caller = block.caller;
+            site.blocks += 1;
+            site.bytes += block.requested;
+        }
+        addr = block.next;
+    }
+    println!("kheap: blocks in use by caller");
+    for site in sites.iter().filter(|site| site.blocks != 0) {
+        println!("{:#010x}: {} blocks, {} bytes", site.caller, site.blocks, site.bytes);
+    }
+    if others.blocks != 0 {
+        println!("others: {} blocks, {} bytes", others.blocks, others.bytes);
+    }
+}
+
+/// Returns true if addr can be the address of a block of the heap
+pub fn is_heap_address(addr: u32) -> bool {
+    unsafe { addr >= KHEAP_ADDR + size_of::<Header>() as u32 && addr < KHEAP_END }
+}
+
+fn block_addr(addr: u32) -> u32 {
+    addr - size_of::<Header>() as u32
+}
+
 fn empty_block(size: usize) -> u32 {
     let mut addr = unsafe { KHEAP_ADDR };
     let mut block = Header::from_ptr(addr as *mut u8);
@@ -150,54 +336,45 @@ fn empty_block(size: usize) -> u32 {
     return addr;
 }
 
-fn kmalloc_table_check(addr: u32, size: usize) -> bool {
+// Rounds addr up to a multiple of TABLE_SIZE, the size of a large page
+fn table_align(addr: u32) -> u32 {
+    (addr + TABLE_SIZE as u32 - 1) & !(TABLE_SIZE as u32 - 1)
+}
+
+// Creates the missing page tables of the frames pages starting at addr in the
+// user directory, returns false if the heap is full
+fn umalloc_table_check(addr: u32, frames: usize) -> bool {
+    let start_idx = addr as usize / TABLE_SIZE;
+    let end_idx = (addr as usize + frames * FRAME_SIZE - 1) / TABLE_SIZE;
     unsafe {
-        let total_size = size + size_of::<Header>();
-        let mut alloc = false;
-        let mut start_idx = addr as usize / TABLE_SIZE;
-        if addr % TABLE_SIZE as u32 != 0 {
-            start_idx += 1;
-        }
-        let mut end_idx = (addr as usize + total_size) / TABLE_SIZE;
-        if Header::from_ptr(addr as *mut u8).next == 0 {
-            end_idx += 1;
-        }
-        let aligned_size = align!(FRAME_SIZE + size_of::<Header>()) - size_of::<Header>();
-        for i in start_idx..end_idx {
-            let block_addr = empty_block(aligned_size);
-            let mut block = Header::from_ptr(block_addr as *mut u8);
-            if block.size >= aligned_size && block.free {
-                let table_addr = INITIAL_PD.new_table(block_addr + FRAME_SIZE as u32);
-                INITIAL_PD[i] = phys!(table_addr) | 0x3;
-                if block.next == 0 {
-                    block.insert_tail(block_addr, aligned_size);
-                } else {
-                    block.insert(block_addr, aligned_size);
+        for i in start_idx..(end_idx + 1) {
+            if (*USER_PD)[i] & 0x1 == 0 {
+                let table_addr = kmalloc_page(FRAME_SIZE);
+                if table_addr == 0 {
+                    return false;
                 }
+                memset(table_addr as *mut u8, 0, FRAME_SIZE);
+                (*USER_PD).set_table(i, table_addr, USER_MODE);
             }
-            alloc = true;
         }
-        return alloc;
     }
+    return true;
 }
 
-fn umalloc_table_check(size: usize) {
-    unsafe {
-        let frame_idx = (*USER_PD).mmap_get_free_area(size);
-        let mut start_idx = frame_idx as usize / TABLE_FSIZE;
-        if frame_idx as usize % TABLE_FSIZE == 0 {
-            let table_addr = kmalloc(FRAME_SIZE) + (FRAME_SIZE - size_of::<Header>()) as u32;
-            (*USER_PD)[start_idx] = phys!(table_addr) | 0x3 | USER_MODE;
-            start_idx += 1;
-        }
-        if frame_idx as usize + size / FRAME_SIZE >= TABLE_FSIZE {
-            let end_idx = (frame_idx as usize + size / FRAME_SIZE) / TABLE_FSIZE;
-            for i in start_idx..end_idx {
-                let table_addr = kmalloc(FRAME_SIZE) + (FRAME_SIZE - size_of::<Header>()) as u32;
-                (*USER_PD)[i] = phys!(table_addr) | 0x3 | USER_MODE;
-            }
+pub fn kheap_stats() -> HeapStats {
+    let mut stats = HeapStats { total: unsafe { KHEAP_SIZE }, used: 0, free: 0, blocks: 0 };
+    let mut addr = unsafe { KHEAP_ADDR };
+    while addr != 0 {
+        let block = Header::from_ptr(addr as *mut u8);
+        if block.free {
+            stats.free += block.size;
+        } else {
+            stats.used += block.size + size_of::<Header>();
+            stats.blocks += 1;
         }
+        addr = block.next;
     }
+    return stats;
 }
 
 pub fn print_kmalloc_list() {
@@ -212,27 +389,66 @@ pub fn print_kmalloc_list() {
     }
 }
 
+unsafe impl GlobalAlloc for KernelAllocator {
+    #[inline(never)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _guard = InterruptGuard::new();
+        // larger alignments get a page aligned block
+        if layout.align() <= KMALLOC_ALIGN {
+            kmalloc_from(layout.size(), return_address(0)) as *mut u8
+        } else if layout.align() <= FRAME_SIZE {
+            let addr = kmalloc_from(layout.size() + FRAME_SIZE, return_address(0));
+            if addr == 0 {
+                return null_mut();
+            }
+            (addr + (FRAME_SIZE - size_of::<Header>()) as u32) as *mut u8
+        } else {
+            null_mut()
+        }
+    }
+    
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _guard = InterruptGuard::new();
+        if layout.align() <= KMALLOC_ALIGN {
+            kfree(ptr as u32);
+        } else {
+            kfree_page(ptr as u32);
+        }
+    }
+}
+
 impl Header {
     fn null(previous: u32, size: usize) -> Header {
         Header {
             previous: previous,
             next: 0,
             size: size,
-            free: true
+            free: true,
+            magic: KHEAP_MAGIC,
+            caller: 0,
+            requested: 0
         }
     }
     
+    // Records the allocation in the header of the block at addr
+    fn tag(mut self, addr: u32, caller: u32, requested: usize) {
+        self.caller = caller;
+        self.requested = requested;
+        unsafe { memcpy(addr as *mut u8, self.as_ptr(), size_of::<Header>()); }
+    }
+    
+    // Blocks allocated outside of kmalloc have no redzone
+    fn redzone_is_intact(&self, addr: u32) -> bool {
+        if self.caller == 0 {
+            return true;
+        }
+        let redzone = (addr + self.requested as u32) as *const u8;
+        (0..REDZONE_SIZE).all(|i| unsafe { *redzone.offset(i as isize) } == REDZONE_BYTE)
+    }
+    
     fn insert(&mut self, addr: u32, size: usize) {
         unsafe {
             let total_size = size + size_of::<Header>();
-            INITIAL_PD.mmap_set_frame(addr / FRAME_SIZE as u32);
-            if (addr as usize % FRAME_SIZE) + total_size > FRAME_SIZE {
-                for i in 1..(total_size / FRAME_SIZE + 1) {
-                    let mut phys_addr = phys!(addr + (i * FRAME_SIZE) as u32);
-                    let mut tmp = 0;
-                    INITIAL_PD.alloc_frame(&mut tmp, &mut phys_addr, KERNEL_MODE);
-                }
-            }
             self.free = false;
             if size == self.size {
                 memcpy(addr as *mut u8, self.as_ptr(), size_of::<Header>());
@@ -259,15 +475,6 @@ impl Header {
             self.size = size;
             self.free = false;
             self.next = addr + total_size as u32;
-            INITIAL_PD.mmap_set_frame(addr / FRAME_SIZE as u32);
-            // alloc new frames if need
-            if (addr as usize % FRAME_SIZE) + total_size > FRAME_SIZE {
-                for i in 1..(total_size / FRAME_SIZE + 1) {
-                    let mut phys_addr = phys!(addr + (i * FRAME_SIZE) as u32);
-                    let mut tmp = 0;
-                    INITIAL_PD.alloc_frame(&mut tmp, &mut phys_addr, KERNEL_MODE);
-                }
-            }
             let tail_size = (KHEAP_END - self.next) as usize - size_of::<Header>();
             let mut tail = Header::null(addr, tail_size);
             memcpy(addr as *mut u8, self.as_ptr(), size_of::<Header>());
@@ -302,8 +509,9 @@ impl Header {
                     }
                 }
                 self.free = true;
+                self.caller = 0;
+                self.requested = 0;
                 memcpy(header_addr as *mut u8, self.as_ptr(), size_of::<Header>());
-                INITIAL_PD.mmap_reset_frame(header_addr / FRAME_SIZE as u32);
             }
         }
     }
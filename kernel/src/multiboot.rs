@@ -1,5 +1,15 @@
 #![allow(dead_code)]
 
+// Flags telling which fields of MultibootInfo are valid
+pub const MULTIBOOT_INFO_MEMORY: u32 = 0x1;
+pub const MULTIBOOT_INFO_MEM_MAP: u32 = 0x40;
+
+// Types of the memory map regions
+pub const MULTIBOOT_MEMORY_AVAILABLE: u32 = 1;
+pub const MULTIBOOT_MEMORY_RESERVED: u32 = 2;
+pub const MULTIBOOT_MEMORY_ACPI_RECLAIMABLE: u32 = 3;
+pub const MULTIBOOT_MEMORY_NVS: u32 = 4;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct MultibootAoutSymbolTable {
@@ -80,3 +90,15 @@ pub struct MultibootModList {
     /* padding to take it to 16 bytes (must be zero) */
     pub pad: u32
 }
+
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct MultibootMmapEntry {
+    /* size of the entry without this field */
+    pub size: u32,
+    pub addr: u64,
+    pub len: u64,
+    /* one of the MULTIBOOT_MEMORY_* values */
+    pub kind: u32
+}
\ No newline at end of file
@@ -6,41 +6,99 @@ use core::ops::{Index, IndexMut};
 use rlibc::memset;
 use vga::*;
 use kheap::*;
+use frame::*;
+use swap::swap_free;
+use varea::AreaTree;
+use x86::{has_nx, rdmsr, wrmsr, MSR_EFER, EFER_NXE};
 
 pub const KERNEL_BASE: u32 = 0xC0000000;
-pub const KERNEL_PAGE_NUMBER: u32 = KERNEL_BASE >> 22;
+pub const KERNEL_PAGE_NUMBER: u32 = KERNEL_BASE / TABLE_SIZE as u32;
 
 const MMAP_SIZE: usize = 0x20000;
 const MEMORY_FSIZE: usize = 0x100000;
-pub const TABLE_FSIZE: usize = 0x400;
-pub const TABLE_SIZE: usize = 0x400000;
 pub const FRAME_SIZE: usize = 0x1000;
 
-pub const KERNEL_MODE: u32 = 0x0;
-pub const USER_MODE: u32 = 0x4;
+// With the pae feature the entries and the physical addresses take 64 bits, a page
+// table maps 2 MB and the directory is made of the four page directories of the
+// page directory pointer table
+#[cfg(not(feature = "pae"))]
+pub type Entry = u32;
+#[cfg(not(feature = "pae"))]
+pub const TABLE_FSIZE: usize = 0x400;
+#[cfg(not(feature = "pae"))]
+pub const DIRECTORY_FSIZE: usize = 0x400;
+
+#[cfg(feature = "pae")]
+pub type Entry = u64;
+#[cfg(feature = "pae")]
+pub const TABLE_FSIZE: usize = 0x200;
+#[cfg(feature = "pae")]
+pub const DIRECTORY_FSIZE: usize = 0x800;
+
+/// Physical address of a frame, it has the size of a page table entry
+pub type PhysAddr = Entry;
+
+pub const TABLE_SIZE: usize = TABLE_FSIZE * FRAME_SIZE;
+pub const PAE: bool = cfg!(feature = "pae");
+
+pub const KERNEL_MODE: Entry = 0x0;
+pub const USER_MODE: Entry = 0x4;
+
+// Directory entry bit of the pages of TABLE_SIZE bytes that need no page table
+pub const PAGE_LARGE: Entry = 0x80;
+// Page table entry bits set by the CPU
+pub const PAGE_ACCESSED: Entry = 0x20;
+pub const PAGE_DIRTY: Entry = 0x40;
+// Available bit marking the not present entries that hold a swap slot
+pub const PAGE_SWAPPED: Entry = 0x200;
+// Available bit marking the frames given to a task by AllocFrame
+pub const PAGE_USER_FRAME: Entry = 0x400;
+// Available bit marking the pages that must not be executed, it sets the NX bit
+// of the entry when the processor supports it in PAE mode
+pub const PAGE_NX: Entry = 0x800;
+#[cfg(feature = "pae")]
+const PAE_ENTRY_NX: u64 = 1 << 63;
 
 static mut INITIAL_MMAP: [u8;MMAP_SIZE] = [0;MMAP_SIZE];
 pub static mut INITIAL_PD: PageDirectory = PageDirectory::null();
+// page table of the end of the kernel area that does not fill a large page
+static mut KERNEL_TAIL_TABLE: PageTable = PageTable::null();
 pub static mut USER_PD: *mut PageDirectory = 0 as *mut PageDirectory;
+// the NX bit is reserved until EFER.NXE is set
+static mut NX_ENABLED: bool = false;
 
 #[derive(Clone, Copy)]
 #[repr(C, align(4096))]
 pub struct PageDirectory {
-    pub tables: *mut PageTable,
-    pub mmap: *mut [u8;MMAP_SIZE]
+    pub tables: *mut Directory,
+    pub mmap: *mut [u8;MMAP_SIZE],
+    // free pages of the area of umalloc, user directories only
+    pub area: *mut AreaTree
+}
+
+#[repr(C, align(4096))]
+pub struct Directory {
+    pub entries: [Entry;DIRECTORY_FSIZE],
+    // CR3 points to this table whose entries point to the four pages of entries
+    #[cfg(feature = "pae")]
+    pub pdpt: [u64;4]
 }
 
 #[derive(Clone, Copy)]
 #[repr(C, align(4096))]
 pub struct PageTable {
-    pub entries: [u32;TABLE_FSIZE]
+    pub entries: [Entry;TABLE_FSIZE]
 }
 
 extern "C" {
     pub fn load_directory(pd_addr: u32);
     pub fn get_cr3() -> u32;
+    pub fn get_cr2() -> u32;
     pub fn get_kernel_start() -> u32;
     pub fn get_kernel_end() -> u32;
+    pub fn invalidate_page(addr: u32);
+    fn enable_write_protect();
+    fn enable_large_pages();
     fn get_kernel_page_directory() -> u32;
     fn get_kernel_page_table() -> u32;
 }
@@ -55,160 +113,274 @@ macro_rules! virt {
 
 pub fn paging_init() {
     unsafe {
-        INITIAL_PD.tables = get_kernel_page_directory() as *mut PageTable;
+        INITIAL_PD.tables = get_kernel_page_directory() as *mut Directory;
         INITIAL_PD.mmap = &mut INITIAL_MMAP as *mut [u8;MMAP_SIZE];
         INITIAL_PD.mmap_set_area(KERNEL_BASE, get_kernel_end());
+        // the kernel must not write to the read-only user pages either
+        enable_write_protect();
+        enable_large_pages();
+        if PAE && has_nx() {
+            wrmsr(MSR_EFER, rdmsr(MSR_EFER) | EFER_NXE);
+            NX_ENABLED = true;
+        }
     }
 }
 
+/// Returns true if the pages marked with PAGE_NX cannot be executed
+pub fn nx_enabled() -> bool {
+    unsafe { NX_ENABLED }
+}
+
 pub fn switch_directory(pd_ptr: *mut PageDirectory) {
     unsafe {
         if pd_ptr as u32 != &INITIAL_PD as *const _ as u32 {
             USER_PD = pd_ptr;
             (*USER_PD).update();
         }
-        load_directory(phys!((*pd_ptr).tables as u32));
+        load_directory((*pd_ptr).cr3());
     }
 }
 
+// The page tables come from the heap, in the first 4 GB, so the virtual address
+// of the table of a directory entry is virt!(entry as u32 &! 0xfff)
 impl Index<usize> for PageDirectory {
-    type Output = u32;
+    type Output = Entry;
 
-    fn index(&self, index: usize) -> &u32 {
-        unsafe { &(*self.tables)[index] }
+    fn index(&self, index: usize) -> &Entry {
+        unsafe { &(*self.tables).entries[index] }
     }
 }
 
 impl IndexMut<usize> for PageDirectory {
-    fn index_mut(&mut self, index: usize) -> &mut u32 {
-        unsafe { &mut (*self.tables)[index] }
+    fn index_mut(&mut self, index: usize) -> &mut Entry {
+        unsafe { &mut (*self.tables).entries[index] }
     }
 }
 
 impl PageDirectory {
     pub const fn null() -> PageDirectory {
         PageDirectory {
-            tables: 0 as *mut PageTable,
-            mmap: 0 as *mut [u8;MMAP_SIZE]
+            tables: 0 as *mut Directory,
+            mmap: 0 as *mut [u8;MMAP_SIZE],
+            area: 0 as *mut AreaTree
         }
     }
     
-    pub fn alloc_frame(&mut self, virt: *mut u32, phys: *mut u32, mode: u32) -> i32 {
+    /// Maps the frame at the physical address phys at the virtual address virt.
+    /// The page table must already exist.
+    pub fn map_page(&mut self, virt: u32, phys: PhysAddr, mode: Entry) {
         unsafe {
-            if *phys < phys!(get_kernel_end()) {
-                println!("alloc_frame: corrupted address");
-                return -1;
-            }
-            *virt = if mode == USER_MODE {
-                self.mmap_alloc_frame(0)
-            } else {
-                self.mmap_alloc_frame(virt!(*phys))
-            };
-            let frame_idx = *virt / FRAME_SIZE as u32;
+            let frame_idx = virt / FRAME_SIZE as u32;
+            let table_idx = frame_idx as usize / TABLE_FSIZE;
+            let entry_idx = frame_idx as usize % TABLE_FSIZE;
+            let table_ptr = self.table(table_idx);
+            (*table_ptr).set(entry_idx, phys | 0x3 | mode);
+            self.mmap_set_frame(frame_idx);
+        }
+    }
+    
+    /// Page table entry of the virtual address addr, 0 if its table does not exist
+    pub fn get_entry(&mut self, addr: u32) -> Entry {
+        unsafe {
+            let frame_idx = addr / FRAME_SIZE as u32;
             let table_idx = frame_idx as usize / TABLE_FSIZE;
             let entry_idx = frame_idx as usize % TABLE_FSIZE;
-            
-            let table_addr = virt!(self[table_idx] &! 0xfff);
-            if *virt != table_addr {
-                let table_ptr = table_addr as *mut PageTable;
-                (*table_ptr)[entry_idx] = *phys | 0x3 | mode;
-                memset(*virt as *mut u8, 0, FRAME_SIZE);
+            if self[table_idx] & 0x1 == 0 {
+                return 0;
+            }
+            if self[table_idx] & PAGE_LARGE != 0 {
+                // entry of the page as if it was mapped by a page table
+                return (self[table_idx] + (entry_idx * FRAME_SIZE) as Entry) &! PAGE_LARGE;
             }
-            return 0;
+            let table_ptr = self.table(table_idx);
+            (*table_ptr).get(entry_idx)
         }
     }
     
-    pub fn free_frame(&mut self, idx: usize) {
+    /// Replaces the page table entry of the virtual address addr.
+    /// The page table must already exist.
+    pub fn set_entry(&mut self, addr: u32, entry: Entry) {
         unsafe {
-            let addr = (idx * FRAME_SIZE) as u32;
-            if addr < get_kernel_end() {
-                println!("free_frame: corrupted address");
-                return;
+            let frame_idx = addr / FRAME_SIZE as u32;
+            let table_idx = frame_idx as usize / TABLE_FSIZE;
+            let entry_idx = frame_idx as usize % TABLE_FSIZE;
+            let table_ptr = self.table(table_idx);
+            (*table_ptr).set(entry_idx, entry);
+            invalidate_page(addr);
+        }
+    }
+    
+    /// Removes the mapping of the virtual address addr, returns the physical
+    /// address of the frame or 0 if it was not mapped
+    pub fn unmap_page(&mut self, addr: u32) -> PhysAddr {
+        unsafe {
+            let frame_idx = addr / FRAME_SIZE as u32;
+            let table_idx = frame_idx as usize / TABLE_FSIZE;
+            let entry_idx = frame_idx as usize % TABLE_FSIZE;
+            if self[table_idx] & 0x1 == 0 {
+                return 0;
+            }
+            let table_ptr = self.table(table_idx);
+            let entry = (*table_ptr).get(entry_idx);
+            if entry & 0x1 == 0 {
+                return 0;
             }
-            let table_idx = idx / TABLE_FSIZE;
-            let entry_idx = idx % TABLE_FSIZE;
-            self.mmap_reset_frame(idx as u32);
-            let table_ptr = virt!(self[table_idx] &! 0xfff) as *mut PageTable;
-            (*table_ptr)[entry_idx] = 0;
-        }    
+            (*table_ptr).set(entry_idx, 0);
+            self.mmap_reset_frame(frame_idx);
+            return entry &! 0xfff;
+        }
     }
     
     pub fn new_directory(&mut self) -> PageDirectory {
+        let tables = kmalloc_page(size_of::<Directory>()) as *mut Directory;
+        unsafe {
+            // the high half of the PAE entries is never written by IndexMut
+            memset(tables as *mut u8, 0, size_of::<Directory>());
+            (*tables).init();
+        }
         PageDirectory {
-            tables: (kmalloc(FRAME_SIZE) + (FRAME_SIZE - size_of::<Header>()) as u32) as *mut PageTable,
-            mmap: kmalloc(MMAP_SIZE) as *mut [u8;MMAP_SIZE]
+            tables: tables,
+            mmap: kmalloc(MMAP_SIZE) as *mut [u8;MMAP_SIZE],
+            area: AreaTree::new()
         }
     }
     
+    /// Physical address loaded in CR3 to use the directory
+    #[cfg(not(feature = "pae"))]
+    pub fn cr3(&self) -> u32 {
+        phys!(self.tables as u32)
+    }
+    
+    /// Physical address loaded in CR3 to use the directory, the one of its
+    /// page directory pointer table
+    #[cfg(feature = "pae")]
+    pub fn cr3(&self) -> u32 {
+        unsafe { phys!(&(*self.tables).pdpt as *const _ as u32) }
+    }
+    
+    /// Frees the directory with its user tables and the frames or swap slots
+    /// still mapped in them
     pub fn free(&mut self) {
-        for i in 0..TABLE_FSIZE {
-            let table_addr = self[i] &! 0xfff;
-            if table_addr != 0 {
-                kfree(virt!(table_addr) - (FRAME_SIZE - size_of::<Header>()) as u32);
-            } else {
-                break;
+        // the user tables are not contiguous, the kernel ones are shared
+        for i in 0..KERNEL_PAGE_NUMBER as usize {
+            if self[i] &! 0xfff != 0 {
+                let table_ptr = self.table(i);
+                for j in 0..TABLE_FSIZE {
+                    let entry = unsafe { (*table_ptr).get(j) };
+                    if entry & PAGE_SWAPPED != 0 {
+                        swap_free(entry);
+                    } else if entry &! 0xfff != 0 {
+                        frame_free(entry &! 0xfff);
+                    }
+                }
+                kfree_page(table_ptr as u32);
             }
         }
-        kfree(self.tables as u32 - (FRAME_SIZE - size_of::<Header>()) as u32);
+        kfree_page(self.tables as u32);
         kfree(self.mmap as u32);
+        kfree(self.area as u32);
     }
     
     pub fn update(&mut self) {
-        for i in KERNEL_PAGE_NUMBER as usize..TABLE_FSIZE {
+        for i in KERNEL_PAGE_NUMBER as usize..DIRECTORY_FSIZE {
             if self[i] == 0 {
                 self[i] = unsafe { INITIAL_PD[i] };
             }
         }
     }
     
-    pub fn new_table(&mut self, addr: u32) -> u32 {
+    /// Maps the kernel area between start and end at start - KERNEL_BASE with
+    /// large pages, in place of the page tables set up by the boot code. The
+    /// bounds of the area must be aligned on TABLE_SIZE bytes.
+    pub fn map_kernel_area(&mut self, start: u32, end: u32) {
+        let mut addr = start;
+        while addr < end {
+            self[addr as usize / TABLE_SIZE] = phys!(addr) as Entry | PAGE_LARGE | 0x3;
+            // the mappings are the same, only the TLB entries change
+            unsafe { invalidate_page(addr); }
+            addr += TABLE_SIZE as u32;
+        }
+        self.mmap_set_area(start, end);
+    }
+    
+    /// Maps the kernel area between start and end at start - KERNEL_BASE with
+    /// the pages of a static page table, for the end of the kernel area that
+    /// is followed by memory which is not RAM. Start must be aligned on
+    /// TABLE_SIZE bytes and end must be in the same large page.
+    pub fn map_kernel_tail(&mut self, start: u32, end: u32) {
         unsafe {
-            let phys_addr = phys!(addr);
-            let virt_addr = virt!(phys_addr);
-            let frame_idx = virt_addr / FRAME_SIZE as u32;
-            let table_idx = frame_idx as usize / TABLE_FSIZE;
-            let entry_idx = frame_idx as usize % TABLE_FSIZE;
-            if self[table_idx] == 0 {
-                // create a temporary table
-                let mut tmp_table = PageTable::null();
-                tmp_table[entry_idx] = phys_addr | 0x3;
-                self[table_idx] = phys!(tmp_table.as_ptr()) | 0x3;
-                // now that the new table is mapped we can modify it
-                let table_ptr = virt_addr as *mut PageTable;
-                memset(virt_addr as *mut u8, 0, size_of::<PageTable>());
-                (*table_ptr)[entry_idx] = phys_addr | 0x3;
-                self[table_idx] = phys_addr | 0x3;
-            } else {
-                let table_ptr = virt!(self[table_idx] &! 0xfff) as *mut PageTable;
-                (*table_ptr)[entry_idx] = phys_addr | 0x3;
-                memset(virt_addr as *mut u8, 0, size_of::<PageTable>());
+            let table = &mut KERNEL_TAIL_TABLE;
+            let mut addr = start;
+            for i in 0..TABLE_FSIZE {
+                let entry = if addr < end { phys!(addr) as Entry | 0x3 } else { 0 };
+                table.set(i, entry);
+                addr += FRAME_SIZE as u32;
+            }
+            self.set_table(start as usize / TABLE_SIZE, table.as_ptr(), KERNEL_MODE);
+            let mut addr = start;
+            while addr < end {
+                invalidate_page(addr);
+                addr += FRAME_SIZE as u32;
             }
-            return virt_addr;
         }
+        self.mmap_set_area(start, end);
     }
     
-    pub fn table_is_empty(&mut self, idx: usize) -> bool {
-        let table_addr = self[idx] &! 0xfff;
-        if table_addr == 0 {
-            return false;
+    /// Unmaps the page at the kernel address addr. The large page holding it is
+    /// first split into a page table taken from the heap. Returns false if the
+    /// heap is full.
+    pub fn unmap_kernel_page(&mut self, addr: u32) -> bool {
+        let table_idx = addr as usize / TABLE_SIZE;
+        if self[table_idx] & PAGE_LARGE != 0 {
+            let table_addr = kmalloc_page(FRAME_SIZE);
+            if table_addr == 0 {
+                return false;
+            }
+            let table_ptr = PageTable::from_ptr(table_addr);
+            let first = self[table_idx] &! (PAGE_LARGE | 0xfff);
+            for i in 0..TABLE_FSIZE {
+                unsafe { (*table_ptr).set(i, first + (i * FRAME_SIZE) as Entry | 0x3); }
+            }
+            self.set_table(table_idx, table_addr, KERNEL_MODE);
         }
-        let table_idx = virt!(table_addr) / FRAME_SIZE as u32;
-        self.mmap_reset_frame(table_idx);
-        
-        // let mmap = unsafe { *self.mmap };
-        // let compact_mmap = unsafe { transmute::<[u8;MMAP_SIZE], [u32;MMAP_SIZE/4]>(mmap) };
-        let size = TABLE_FSIZE / 8;
-        let mmap_idx = size * idx;
-        let mut is_empty = true;
-        
-        for i in mmap_idx..(mmap_idx+size) {
-            if unsafe { (*self.mmap)[i] } != 0 {
-                is_empty = false;
-                break;
+        self.set_entry(addr, 0);
+        return true;
+    }
+    
+    /// Sets the directory entry idx to the page table at the virtual address
+    /// table_addr, taken from the heap
+    pub fn set_table(&mut self, idx: usize, table_addr: u32, mode: Entry) {
+        self[idx] = phys!(table_addr) as Entry | 0x3 | mode;
+    }
+    
+    // Virtual address of the page table of the directory entry idx
+    fn table(&self, idx: usize) -> *mut PageTable {
+        virt!(self[idx] as u32 &! 0xfff) as *mut PageTable
+    }
+    
+    /// Number of user frames mapped in the directory
+    pub fn user_frames(&mut self) -> usize {
+        let mut cnt = 0;
+        for i in 0..KERNEL_PAGE_NUMBER as usize {
+            if self[i] &! 0xfff != 0 {
+                let table_ptr = self.table(i);
+                for j in 0..TABLE_FSIZE {
+                    if unsafe { (*table_ptr).get(j) } & 0x1 != 0 {
+                        cnt += 1;
+                    }
+                }
             }
         }
-        self.mmap_set_frame(table_idx);
-        return is_empty;
+        return cnt;
+    }
+    
+    /// Number of frames marked as used in the directory memory map
+    pub fn mmap_used_frames(&mut self) -> usize {
+        let mut cnt = 0;
+        for i in 0..MMAP_SIZE {
+            cnt += unsafe { (*self.mmap)[i] }.count_ones() as usize;
+        }
+        return cnt;
     }
 
     pub fn mmap_alloc_frame(&mut self, addr: u32) -> u32 {
@@ -230,25 +402,6 @@ impl PageDirectory {
         return 0;
     }
     
-    pub fn mmap_get_free_area(&mut self, size: usize) -> u32 {
-        let mut cnt = 0;
-        let mut start_frame = 0;
-        for i in 0..MEMORY_FSIZE {
-            if self.mmap_frame_state(i as u32) == 0 {
-                if cnt == 0 {
-                    start_frame = i as u32;
-                }
-                cnt += 1;
-            } else {
-                cnt = 0;
-            }
-            if cnt == size / FRAME_SIZE {
-                break;
-            }
-        }
-        return start_frame;
-    }
-
     pub fn mmap_set_frame(&mut self, frame_id: u32) {
         let mmap_id = frame_id / 8;
         let bit_offset = frame_id % 8;
@@ -279,27 +432,45 @@ impl PageDirectory {
     }
 }
 
-impl Index<usize> for PageTable {
-    type Output = u32;
-
-    fn index(&self, index: usize) -> &u32 {
-        &self.entries[index]
-    }
-}
-
-impl IndexMut<usize> for PageTable {
-    fn index_mut(&mut self, index: usize) -> &mut u32 {
-        &mut self.entries[index]
+impl Directory {
+    // Points the page directory pointer table to the four page directories
+    #[cfg(feature = "pae")]
+    unsafe fn init(&mut self) {
+        for i in 0..4 {
+            let dir_addr = &self.entries[i * FRAME_SIZE / size_of::<Entry>()] as *const Entry as u32;
+            self.pdpt[i] = (phys!(dir_addr) | 0x1) as u64;
+        }
     }
+    
+    #[cfg(not(feature = "pae"))]
+    unsafe fn init(&mut self) {}
 }
 
 impl PageTable {
-    fn null() -> PageTable {
+    pub const fn null() -> PageTable {
         PageTable {
             entries: [0;TABLE_FSIZE]
         }
     }
     
+    /// Entry idx of the table, a PAE entry without its NX bit
+    #[cfg(not(feature = "pae"))]
+    pub fn get(&self, idx: usize) -> Entry {
+        self.entries[idx]
+    }
+    
+    /// Entry idx of the table, a PAE entry without its NX bit
+    #[cfg(feature = "pae")]
+    pub fn get(&self, idx: usize) -> Entry {
+        self.entries[idx] &! PAE_ENTRY_NX
+    }
+    
+    /// Replaces the entry idx of the table, PAGE_NX also sets the NX bit
+    /// of a PAE entry if it is enabled
+    pub fn set(&mut self, idx: usize, entry: Entry) {
+        self.entries[idx] = to_entry(entry);
+    }
+    
     pub fn from_ptr(addr: u32) -> *mut PageTable {
         addr as *mut PageTable
     }
@@ -307,4 +478,18 @@ impl PageTable {
     pub fn as_ptr(&mut self) -> u32 {
         self as *const PageTable as u32
     }
+}
+
+#[cfg(not(feature = "pae"))]
+fn to_entry(entry: Entry) -> Entry {
+    entry
+}
+
+#[cfg(feature = "pae")]
+fn to_entry(entry: Entry) -> Entry {
+    if entry & PAGE_NX != 0 && nx_enabled() {
+        entry | PAE_ENTRY_NX
+    } else {
+        entry
+    }
 }
\ No newline at end of file
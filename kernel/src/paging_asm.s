@@ -7,10 +7,14 @@ extern kernel_pt
 
 global load_directory
 global get_cr3
+global get_cr2
 global get_kernel_start
 global get_kernel_end
 global get_kernel_page_directory
 global get_kernel_page_table
+global invalidate_page
+global enable_write_protect
+global enable_large_pages
 
 section .text:          ; start of the text (code) section
 
@@ -37,6 +41,15 @@ get_cr3:
     leave
     ret
     
+get_cr2:
+    push ebp
+    mov ebp, esp
+
+    mov eax, cr2        ; address that caused the last page fault
+
+    leave
+    ret
+    
 get_kernel_start:
     push ebp
     mov ebp, esp
@@ -73,4 +86,29 @@ get_kernel_page_table:
     add eax, KERNEL_BASE
     
     leave
+    ret
+    
+invalidate_page:
+    push ebp
+    mov ebp, esp
+    
+    mov eax, [esp+8]    ; Get the virtual address, passed as a parameter.
+    invlpg [eax]        ; flush its entry from the TLB
+    
+    leave
+    ret
+    
+; Sets CR0.WP so that the read-only pages are also read-only in ring 0
+enable_write_protect:
+    mov eax, cr0
+    or  eax, 1 << 16    ; set WP
+    mov cr0, eax
+    ret
+    
+; Sets CR4.PSE so that the directory entries with PAGE_LARGE map 4 MB pages,
+; PAE mode uses 2 MB pages without it
+enable_large_pages:
+    mov eax, cr4
+    or  eax, 1 << 4     ; set PSE
+    mov cr4, eax
     ret
\ No newline at end of file
@@ -0,0 +1,227 @@
+//! Kernel state exposed as text files under /proc
+#![allow(dead_code)]
+
+use core::fmt::{Error, Write};
+use core::cmp::min;
+use core::str::FromStr;
+use rlibc::memcpy;
+use paging::*;
+use kheap::*;
+use slab::*;
+use frame::*;
+use idt::*;
+use timer::*;
+use task::*;
+use vm::*;
+use shm::SHM_OBJECTS;
+use swap::swap_stats;
+use fs::fd_count;
+use trace::{trace_size, trace_read};
+use common::*;
+
+const PROC_BUFFER_SIZE: usize = 1024;
+const IRQ_NAMES: [&str;IRQ_NB] = [
+    "timer", "keyboard", "cascade", "com2", "com1", "lpt2", "floppy", "lpt1",
+    "rtc", "free", "free", "free", "mouse", "fpu", "ide0", "ide1"
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcFile {
+    Meminfo,
+    Interrupts,
+    Uptime,
+    Slabinfo,
+    Trace,
+    Status(u8),
+    Maps(u8)
+}
+
+struct ProcBuffer {
+    data: [u8;PROC_BUFFER_SIZE],
+    len: usize
+}
+
+/// Returns the proc file associated to a path of the form /proc/<name>
+pub fn proc_lookup(filename: &str) -> Option<ProcFile> {
+    if !filename.starts_with(PROC_PATH) {
+        return None;
+    }
+    match &filename[PROC_PATH.len()..] {
+        "meminfo" => Some(ProcFile::Meminfo),
+        "interrupts" => Some(ProcFile::Interrupts),
+        "uptime" => Some(ProcFile::Uptime),
+        "slabinfo" => Some(ProcFile::Slabinfo),
+        "trace" => Some(ProcFile::Trace),
+        name => {
+            let mut parts = name.split('/');
+            let pid = match parts.next().map(u8::from_str) {
+                Some(Ok(pid)) => pid,
+                _ => return None
+            };
+            let file = match parts.next() {
+                Some("status") => ProcFile::Status(pid),
+                Some("maps") => ProcFile::Maps(pid),
+                _ => return None
+            };
+            if parts.next().is_some() || pid as usize >= TASKS_NB || unsafe { TASKS[pid as usize].state } == TaskState::Free {
+                return None;
+            }
+            Some(file)
+        }
+    }
+}
+
+/// Size in bytes of the current content of the proc file
+pub fn proc_size(file: ProcFile) -> usize {
+    if file == ProcFile::Trace {
+        return trace_size();
+    }
+    let mut buffer = ProcBuffer::new();
+    proc_render(file, &mut buffer);
+    return buffer.len;
+}
+
+/// Reads at most n bytes of the proc file starting at the position pos.
+/// The content is generated again on each read, except for the trace which
+/// is too large for the buffer and is read from its ring buffer.
+pub fn proc_read(file: ProcFile, pos: usize, buf: *mut u8, n: usize) -> i32 {
+    if file == ProcFile::Trace {
+        return trace_read(pos, buf, n) as i32;
+    }
+    let mut buffer = ProcBuffer::new();
+    proc_render(file, &mut buffer);
+    if pos >= buffer.len {
+        return 0;
+    }
+    let cnt = min(n, buffer.len - pos);
+    unsafe { memcpy(buf, &buffer.data[pos], cnt); }
+    return cnt as i32;
+}
+
+fn proc_render(file: ProcFile, buffer: &mut ProcBuffer) {
+    match file {
+        ProcFile::Meminfo => render_meminfo(buffer),
+        ProcFile::Interrupts => render_interrupts(buffer),
+        ProcFile::Uptime => render_uptime(buffer),
+        ProcFile::Slabinfo => render_slabinfo(buffer),
+        ProcFile::Trace => Ok(()),
+        ProcFile::Status(pid) => render_status(pid as usize, buffer),
+        ProcFile::Maps(pid) => render_maps(pid as usize, buffer)
+    }.ok();
+}
+
+fn render_meminfo(buffer: &mut ProcBuffer) -> Result<(), Error> {
+    let stats = kheap_stats();
+    let frames = unsafe { INITIAL_PD.mmap_used_frames() };
+    let memory = frame_stats();
+    let swap = swap_stats();
+    writeln!(buffer, "MemTotal: {} kB", memory.total * FRAME_SIZE / 1024)?;
+    writeln!(buffer, "MemFree: {} kB", memory.free * FRAME_SIZE / 1024)?;
+    write!(buffer, "FreeBlocks:")?;
+    for order in 0..(MAX_ORDER + 1) {
+        write!(buffer, " {}", memory.blocks[order])?;
+    }
+    writeln!(buffer)?;
+    writeln!(buffer, "HeapTotal: {} kB", stats.total / 1024)?;
+    writeln!(buffer, "HeapUsed: {} kB", stats.used / 1024)?;
+    writeln!(buffer, "HeapFree: {} kB", stats.free / 1024)?;
+    writeln!(buffer, "HeapBlocks: {}", stats.blocks)?;
+    writeln!(buffer, "SwapTotal: {} kB", swap.total * FRAME_SIZE / 1024)?;
+    writeln!(buffer, "SwapFree: {} kB", (swap.total - swap.used) * FRAME_SIZE / 1024)?;
+    writeln!(buffer, "SwapOut: {}", swap.swapped_out)?;
+    writeln!(buffer, "SwapIn: {}", swap.swapped_in)?;
+    writeln!(buffer, "FramesUsed: {}", frames)?;
+    writeln!(buffer, "FrameSize: {}", FRAME_SIZE)
+}
+
+fn render_interrupts(buffer: &mut ProcBuffer) -> Result<(), Error> {
+    for irq in 0..IRQ_NB {
+        writeln!(buffer, "{:>3}: {:>10} {}", irq, irq_count(irq), IRQ_NAMES[irq])?;
+    }
+    Ok(())
+}
+
+fn render_uptime(buffer: &mut ProcBuffer) -> Result<(), Error> {
+    let ticks = get_ticks();
+    let freq = get_freq();
+    writeln!(buffer, "{}.{:02}", ticks / freq, (ticks % freq) * 100 / freq)
+}
+
+fn render_slabinfo(buffer: &mut ProcBuffer) -> Result<(), Error> {
+    writeln!(buffer, "{:<14} {:>5} {:>7} {:>7} {:>5} {:>8} {:>8}", "# name", "size", "active", "total", "slabs", "allocs", "frees")?;
+    let mut result = Ok(());
+    slab_caches(|cache| {
+        if result.is_ok() {
+            let stats = cache.stats;
+            result = writeln!(buffer, "{:<14} {:>5} {:>7} {:>7} {:>5} {:>8} {:>8}", cache.name, cache.size,
+                stats.active, stats.total, stats.slabs, stats.allocs, stats.frees);
+        }
+    });
+    result
+}
+
+fn render_status(pid: usize, buffer: &mut ProcBuffer) -> Result<(), Error> {
+    let task = unsafe { &mut TASKS[pid] };
+    let state = match task.state {
+        TaskState::Free => "free",
+        TaskState::Running => "running",
+        TaskState::Waiting => "waiting"
+    };
+    writeln!(buffer, "Name: {}", bytes_to_str(&task.name).unwrap_or("?"))?;
+    writeln!(buffer, "Pid: {}", pid)?;
+    writeln!(buffer, "PPid: {}", task.parent)?;
+    writeln!(buffer, "State: {}", state)?;
+    if task.state != TaskState::Free {
+        writeln!(buffer, "Frames: {}", task.pd.user_frames())?;
+    }
+    writeln!(buffer, "Fds: {}", fd_count(pid as i8))
+}
+
+fn render_maps(pid: usize, buffer: &mut ProcBuffer) -> Result<(), Error> {
+    let space = unsafe { &ADDRESS_SPACES[pid] };
+    // regions are listed by address
+    let mut addr = 0;
+    loop {
+        let next = space.regions.iter()
+            .filter(|region| region.kind != RegionKind::Free && region.start >= addr)
+            .min_by_key(|region| region.start);
+        let region = match next {
+            Some(region) => region,
+            None => return Ok(())
+        };
+        let prot = |flag, c| if region.prot & flag != 0 { c } else { '-' };
+        let name = match region.kind {
+            RegionKind::Code => "[code]",
+            RegionKind::Data => "[data]",
+            RegionKind::Stack => "[stack]",
+            RegionKind::Heap => "[heap]",
+            RegionKind::File => bytes_to_str(&region.file.as_ref().unwrap().stat.name).unwrap_or("?"),
+            RegionKind::Shared => bytes_to_str(unsafe { &SHM_OBJECTS[region.shm.unwrap().id].name }).unwrap_or("?"),
+            _ => ""
+        };
+        writeln!(buffer, "{:08x}-{:08x} {}{}{} {}", region.start, region.end,
+            prot(PROT_READ, 'r'), prot(PROT_WRITE, 'w'), prot(PROT_EXEC, 'x'), name)?;
+        addr = region.start + 1;
+    }
+}
+
+impl ProcBuffer {
+    fn new() -> ProcBuffer {
+        ProcBuffer {
+            data: [0;PROC_BUFFER_SIZE],
+            len: 0
+        }
+    }
+}
+
+impl Write for ProcBuffer {
+    fn write_str(&mut self, s: &str) -> Result<(), Error> {
+        let cnt = min(s.len(), PROC_BUFFER_SIZE - self.len);
+        self.data[self.len..self.len+cnt].copy_from_slice(&s.as_bytes()[0..cnt]);
+        self.len += cnt;
+        if cnt < s.len() {
+            return Err(Error);
+        }
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,95 @@
+//! Real time clock read from the CMOS
+#![allow(dead_code)]
+
+use pio::*;
+use common::*;
+
+// CMOS ports
+const CMOS_CMD: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+// CMOS registers
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0a;
+const RTC_STATUS_B: u8 = 0x0b;
+
+// Status B flags
+const RTC_24_HOURS: u8 = 0x02;
+const RTC_BINARY: u8 = 0x04;
+
+static mut BOOT_TIME: u32 = 0;
+
+/// Reads the boot date from the CMOS
+pub fn rtc_init() {
+    unsafe { BOOT_TIME = rtc_read().to_timestamp(); }
+}
+
+/// Seconds since 1970-01-01 00:00:00 at boot
+pub fn boot_time() -> u32 {
+    unsafe { BOOT_TIME }
+}
+
+/// Current number of seconds since 1970-01-01 00:00:00
+pub fn rtc_time() -> u32 {
+    rtc_read().to_timestamp()
+}
+
+/// Reads the current date from the CMOS.
+/// Registers are read until two consecutive reads match, so that an update
+/// of the clock in the middle of the read is not seen.
+pub fn rtc_read() -> DateTime {
+    let mut date = read_registers();
+    loop {
+        let last = date;
+        date = read_registers();
+        if date == last {
+            break;
+        }
+    }
+    
+    let status = read_register(RTC_STATUS_B);
+    if status & RTC_BINARY == 0 {
+        date.second = bcd_to_binary(date.second);
+        date.minute = bcd_to_binary(date.minute);
+        date.hour = bcd_to_binary(date.hour & 0x7f) | (date.hour & 0x80);
+        date.day = bcd_to_binary(date.day);
+        date.month = bcd_to_binary(date.month);
+        date.year = bcd_to_binary(date.year);
+    }
+    // 12 hours clock, bit 7 is set for pm
+    if status & RTC_24_HOURS == 0 {
+        let pm = if date.hour & 0x80 != 0 { 12 } else { 0 };
+        date.hour = (date.hour & 0x7f) % 12 + pm;
+    }
+    date.year += 2000;
+    return date;
+}
+
+fn read_registers() -> DateTime {
+    // wait until no update is in progress
+    while read_register(RTC_STATUS_A) & 0x80 != 0 {}
+    DateTime {
+        year: read_register(RTC_YEAR) as u32,
+        month: read_register(RTC_MONTH) as u32,
+        day: read_register(RTC_DAY) as u32,
+        hour: read_register(RTC_HOURS) as u32,
+        minute: read_register(RTC_MINUTES) as u32,
+        second: read_register(RTC_SECONDS) as u32
+    }
+}
+
+fn read_register(reg: u8) -> u8 {
+    unsafe {
+        outb(CMOS_CMD, reg);
+        inb(CMOS_DATA)
+    }
+}
+
+fn bcd_to_binary(value: u32) -> u32 {
+    (value & 0x0f) + (value >> 4) * 10
+}
\ No newline at end of file
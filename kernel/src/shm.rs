@@ -0,0 +1,101 @@
+//! Named shared memory objects.
+//! An object is a block of physically contiguous frames taken from the buddy
+//! allocator. Tasks get a file descriptor on it with shm_open and map its
+//! frames with mmap, so every mapping sees the same memory. Descriptors and
+//! mappings hold a reference on the object, whose frames are given back
+//! when the last one goes away.
+#![allow(dead_code)]
+
+use paging::*;
+use frame::*;
+use common::*;
+
+pub const SHM_OBJECTS_NB: usize = 16;
+pub const SHM_MAX_SIZE: usize = FRAME_SIZE << MAX_ORDER;
+
+pub static mut SHM_OBJECTS: [ShmObject;SHM_OBJECTS_NB] = [ShmObject::null();SHM_OBJECTS_NB];
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShmObject {
+    pub name: [u8;MAX_FILENAME_LENGTH],
+    pub size: usize,
+    pub refs: usize,
+    phys: PhysAddr,
+    order: usize
+}
+
+/// Returns the index of the object called name with a new reference on it.
+/// The object is created with size bytes of zeroed memory if it does not
+/// exist, the size of an existing object never changes.
+pub fn shm_open(name: &str, size: usize) -> Result<usize, Error> {
+    unsafe {
+        for (id, object) in SHM_OBJECTS.iter_mut().enumerate() {
+            if object.refs != 0 && bytes_to_str(&object.name) == Ok(name) {
+                object.refs += 1;
+                return Ok(id);
+            }
+        }
+        if name.is_empty() || name.len() > MAX_FILENAME_LENGTH || size == 0 || size > SHM_MAX_SIZE {
+            return Err(Error::Invalid);
+        }
+        let id = SHM_OBJECTS.iter().position(|object| object.refs == 0).ok_or(Error::NoMemory)?;
+        let frames = (size + FRAME_SIZE - 1) / FRAME_SIZE;
+        let order = frame_order(frames);
+        let phys = frame_alloc_order(order);
+        if phys == 0 {
+            return Err(Error::NoMemory);
+        }
+        for i in 0..(1 << order) {
+            frame_zero(phys + (i * FRAME_SIZE) as PhysAddr);
+        }
+        let object = &mut SHM_OBJECTS[id];
+        *object = ShmObject::null();
+        object.name[0..name.len()].copy_from_slice(name.as_bytes());
+        object.size = frames * FRAME_SIZE;
+        object.refs = 1;
+        object.phys = phys;
+        object.order = order;
+        return Ok(id);
+    }
+}
+
+/// Takes a new reference on the object, for a new mapping
+pub fn shm_ref(id: usize) {
+    unsafe { SHM_OBJECTS[id].refs += 1; }
+}
+
+/// Drops a reference on the object, its frames are freed with the last one
+pub fn shm_unref(id: usize) {
+    unsafe {
+        let object = &mut SHM_OBJECTS[id];
+        if object.refs == 0 {
+            return;
+        }
+        object.refs -= 1;
+        if object.refs == 0 {
+            frame_free_order(object.phys, object.order);
+            *object = ShmObject::null();
+        }
+    }
+}
+
+/// Physical address of the frame at the position pos of the object
+pub fn shm_frame(id: usize, pos: usize) -> PhysAddr {
+    unsafe { SHM_OBJECTS[id].phys + (pos & !(FRAME_SIZE - 1)) as PhysAddr }
+}
+
+pub fn shm_size(id: usize) -> usize {
+    unsafe { SHM_OBJECTS[id].size }
+}
+
+impl ShmObject {
+    const fn null() -> ShmObject {
+        ShmObject {
+            name: [0;MAX_FILENAME_LENGTH],
+            size: 0,
+            refs: 0,
+            phys: 0,
+            order: 0
+        }
+    }
+}
\ No newline at end of file
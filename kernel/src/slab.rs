@@ -0,0 +1,234 @@
+//! Slab allocator for small kernel objects.
+//! A cache hands out objects of a single size carved from slabs: pages of
+//! the heap starting with a Slab header followed by the objects. The free
+//! objects of a slab are linked through their first word. A cache keeps one
+//! empty slab, so that allocating and freeing a single object does not take
+//! a page from the heap each time. Other empty slabs go back to the heap.
+#![allow(dead_code)]
+
+use rlibc::memset;
+use paging::*;
+use kheap::*;
+use vga::*;
+
+/// Larger allocations go directly to the heap
+pub const SLAB_MAX_SIZE: usize = 1024;
+pub const KMALLOC_CACHES_NB: usize = 7;
+pub const MAX_CACHES: usize = 16;
+
+// Objects start after the header
+const SLAB_HEADER_SIZE: usize = 48;
+// never a heap address, which is what the heap blocks start with
+const SLAB_MAGIC: u32 = 0x51ab51ab;
+const MIN_OBJECT_SIZE: usize = 16;
+
+pub static mut KMALLOC_CACHES: [SlabCache;KMALLOC_CACHES_NB] = [
+    SlabCache::new("kmalloc-16", 16),
+    SlabCache::new("kmalloc-32", 32),
+    SlabCache::new("kmalloc-64", 64),
+    SlabCache::new("kmalloc-128", 128),
+    SlabCache::new("kmalloc-256", 256),
+    SlabCache::new("kmalloc-512", 512),
+    SlabCache::new("kmalloc-1024", 1024)
+];
+static mut CACHES: [*mut SlabCache;MAX_CACHES] = [0 as *mut SlabCache;MAX_CACHES];
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    pub slabs: usize,
+    pub active: usize,
+    pub total: usize,
+    pub allocs: usize,
+    pub frees: usize
+}
+
+/// Objects of a single size. Caches are meant to be statics, they are listed
+/// in /proc/slabinfo once they have been used.
+pub struct SlabCache {
+    pub name: &'static str,
+    pub size: usize,
+    pub stats: SlabStats,
+    slabs: u32,
+    registered: bool
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Slab {
+    magic: u32,
+    next: u32,
+    free: u32,
+    used: u32,
+    cache: u32
+}
+
+impl SlabCache {
+    pub const fn new(name: &'static str, size: usize) -> SlabCache {
+        SlabCache {
+            name: name,
+            // objects must hold the free list link and stay aligned
+            size: (size + MIN_OBJECT_SIZE - 1) / MIN_OBJECT_SIZE * MIN_OBJECT_SIZE,
+            stats: SlabStats { slabs: 0, active: 0, total: 0, allocs: 0, frees: 0 },
+            slabs: 0,
+            registered: false
+        }
+    }
+    
+    /// Returns the address of a zeroed object, 0 if the heap is full
+    pub fn alloc(&mut self) -> u32 {
+        unsafe {
+            if !self.registered {
+                self.register();
+            }
+            let mut slab_addr = self.slabs;
+            while slab_addr != 0 && (*(slab_addr as *const Slab)).free == 0 {
+                slab_addr = (*(slab_addr as *const Slab)).next;
+            }
+            if slab_addr == 0 {
+                slab_addr = self.grow();
+                if slab_addr == 0 {
+                    return 0;
+                }
+            }
+            let slab = slab_addr as *mut Slab;
+            let addr = (*slab).free;
+            (*slab).free = *(addr as *const u32);
+            (*slab).used += 1;
+            self.stats.active += 1;
+            self.stats.allocs += 1;
+            memset(addr as *mut u8, 0, self.size);
+            return addr;
+        }
+    }
+    
+    pub fn free(&mut self, addr: u32) {
+        unsafe {
+            let slab = slab_of(addr);
+            if (*slab).magic != SLAB_MAGIC || (*slab).cache != self as *mut SlabCache as u32 {
+                println!("slab: {}: {:#x} does not belong to the cache", self.name, addr);
+                return;
+            }
+            *(addr as *mut u32) = (*slab).free;
+            (*slab).free = addr;
+            (*slab).used -= 1;
+            self.stats.active -= 1;
+            self.stats.frees += 1;
+            if (*slab).used == 0 && self.has_empty_slab(slab as u32) {
+                self.release(slab as u32);
+            }
+        }
+    }
+    
+    fn objects_per_slab(&self) -> usize {
+        (FRAME_SIZE - SLAB_HEADER_SIZE) / self.size
+    }
+    
+    unsafe fn register(&mut self) {
+        for cache in CACHES.iter_mut() {
+            if cache.is_null() {
+                *cache = self as *mut SlabCache;
+                break;
+            }
+        }
+        self.registered = true;
+    }
+    
+    // Whether a slab other than slab_addr has no object in use
+    unsafe fn has_empty_slab(&self, slab_addr: u32) -> bool {
+        let mut addr = self.slabs;
+        while addr != 0 {
+            if addr != slab_addr && (*(addr as *const Slab)).used == 0 {
+                return true;
+            }
+            addr = (*(addr as *const Slab)).next;
+        }
+        return false;
+    }
+    
+    // Adds a slab at the head of the list
+    unsafe fn grow(&mut self) -> u32 {
+        let slab_addr = kmalloc_page(FRAME_SIZE);
+        if slab_addr == 0 {
+            return 0;
+        }
+        let objects = self.objects_per_slab();
+        let first = slab_addr + SLAB_HEADER_SIZE as u32;
+        for i in 0..objects {
+            let obj = first + (i * self.size) as u32;
+            *(obj as *mut u32) = if i + 1 < objects { obj + self.size as u32 } else { 0 };
+        }
+        *(slab_addr as *mut Slab) = Slab {
+            magic: SLAB_MAGIC,
+            next: self.slabs,
+            free: first,
+            used: 0,
+            cache: self as *mut SlabCache as u32
+        };
+        self.slabs = slab_addr;
+        self.stats.slabs += 1;
+        self.stats.total += objects;
+        return slab_addr;
+    }
+    
+    // Gives an empty slab back to the heap
+    unsafe fn release(&mut self, slab_addr: u32) {
+        let next = (*(slab_addr as *const Slab)).next;
+        if self.slabs == slab_addr {
+            self.slabs = next;
+        } else {
+            let mut addr = self.slabs;
+            while (*(addr as *const Slab)).next != slab_addr {
+                addr = (*(addr as *const Slab)).next;
+            }
+            (*(addr as *mut Slab)).next = next;
+        }
+        (*(slab_addr as *mut Slab)).magic = 0;
+        self.stats.slabs -= 1;
+        self.stats.total -= self.objects_per_slab();
+        kfree_page(slab_addr);
+    }
+}
+
+/// Allocates size bytes from the smallest kmalloc cache that fits
+pub fn slab_alloc(size: usize) -> u32 {
+    let mut idx = 0;
+    while idx < KMALLOC_CACHES_NB - 1 && unsafe { KMALLOC_CACHES[idx].size } < size {
+        idx += 1;
+    }
+    unsafe { KMALLOC_CACHES[idx].alloc() }
+}
+
+/// Gives back an object allocated by any cache
+pub fn slab_free(addr: u32) {
+    unsafe {
+        let slab = slab_of(addr);
+        if (*slab).magic != SLAB_MAGIC {
+            println!("slab_free: {:#x} is not a slab object", addr);
+            return;
+        }
+        (*((*slab).cache as *mut SlabCache)).free(addr);
+    }
+}
+
+/// Returns true if addr is the address of an object of a slab, whose page
+/// starts with the slab magic. The heap blocks start with a link to the
+/// previous block instead.
+pub fn is_slab_object(addr: u32) -> bool {
+    addr as usize % FRAME_SIZE >= SLAB_HEADER_SIZE && is_heap_address(addr) &&
+        unsafe { (*slab_of(addr)).magic == SLAB_MAGIC }
+}
+
+/// Calls f with each cache used so far
+pub fn slab_caches<F: FnMut(&SlabCache)>(mut f: F) {
+    unsafe {
+        for cache in CACHES.iter() {
+            if !cache.is_null() {
+                f(&**cache);
+            }
+        }
+    }
+}
+
+fn slab_of(addr: u32) -> *mut Slab {
+    (addr &! (FRAME_SIZE as u32 - 1)) as *mut Slab
+}
\ No newline at end of file
@@ -0,0 +1,227 @@
+//! Swapping of the user pages to the second disk of the primary bus.
+//! When the frame allocator is empty, the clock algorithm picks a page of a
+//! user task that has not been accessed recently. Its content goes to a slot
+//! of the swap disk and its page table entry keeps the slot number with the
+//! PAGE_SWAPPED bit instead of the present bit, so that the page fault
+//! handler can read it back on the next access. Pages that were never
+//! written since they were mapped are still zeroed and take no slot.
+#![allow(dead_code)]
+
+use core::cmp::{min, max};
+use paging::*;
+use frame::*;
+use ide::*;
+use task::*;
+use vm::*;
+use vga::*;
+
+pub const SWAP_MAX_SLOTS: usize = 0x8000;
+
+const SECTORS_PER_SLOT: u32 = (FRAME_SIZE / SECTOR_SIZE) as u32;
+// slot of the swapped pages that are still zeroed, never written on the disk
+const ZERO_SLOT: u32 = 0;
+
+static mut SWAP_SLOTS: usize = 0;
+static mut SWAP_USED: usize = 0;
+static mut SLOT_BITMAP: [u32;SWAP_MAX_SLOTS/32] = [0;SWAP_MAX_SLOTS/32];
+static mut SWAPPED_OUT: usize = 0;
+static mut SWAPPED_IN: usize = 0;
+// position of the clock hand
+static mut HAND_TASK: usize = 0;
+static mut HAND_REGION: usize = 0;
+static mut HAND_ADDR: u32 = 0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SwapStats {
+    pub total: usize,
+    pub used: usize,
+    pub swapped_out: usize,
+    pub swapped_in: usize
+}
+
+/// Uses the slave disk as swap if there is one
+pub fn swap_init() {
+    unsafe {
+        SWAP_SLOTS = match ide_identify(IDE_SLAVE) {
+            Some(sectors) => min((sectors / SECTORS_PER_SLOT) as usize, SWAP_MAX_SLOTS),
+            None => 0
+        };
+        if SWAP_SLOTS > 1 {
+            SLOT_BITMAP[0] |= 1 << ZERO_SLOT;
+            println!("Swap = {} kB on hdb", (SWAP_SLOTS - 1) * FRAME_SIZE / 1024);
+        } else {
+            SWAP_SLOTS = 0;
+            println!("No swap");
+        }
+    }
+}
+
+/// Returns the physical address of a free frame for a user page. Pages of the
+/// user tasks are swapped out if the memory is full, 0 if none can be.
+pub fn swap_frame_alloc() -> PhysAddr {
+    loop {
+        let addr = frame_alloc();
+        if addr != 0 || unsafe { !swap_out() } {
+            return addr;
+        }
+    }
+}
+
+/// Reads back the swapped page at the virtual address addr of the directory,
+/// entry being its page table entry. flags are the flags of the new entry.
+pub fn swap_in(pd: &mut PageDirectory, addr: u32, entry: Entry, flags: Entry) -> bool {
+    let phys_addr = swap_frame_alloc();
+    if phys_addr == 0 {
+        return false;
+    }
+    let slot = (entry >> 12) as u32;
+    if slot == ZERO_SLOT {
+        frame_zero(phys_addr);
+        pd.set_entry(addr, phys_addr | flags);
+    } else {
+        unsafe {
+            read_slot(slot, phys_addr);
+            release_slot(slot);
+        }
+        // the page must be written again to be swapped out
+        pd.set_entry(addr, phys_addr | flags | PAGE_DIRTY);
+    }
+    unsafe { SWAPPED_IN += 1; }
+    return true;
+}
+
+/// Gives back the slot of a swapped page table entry
+pub fn swap_free(entry: Entry) {
+    let slot = (entry >> 12) as u32;
+    if slot != ZERO_SLOT {
+        unsafe { release_slot(slot); }
+    }
+}
+
+pub fn swap_stats() -> SwapStats {
+    unsafe {
+        SwapStats {
+            total: if SWAP_SLOTS > 0 { SWAP_SLOTS - 1 } else { 0 },
+            used: SWAP_USED,
+            swapped_out: SWAPPED_OUT,
+            swapped_in: SWAPPED_IN
+        }
+    }
+}
+
+// Swaps out the first page found by the clock hand that was not accessed
+// since its last visit. Returns false if there is no such page or no free slot.
+unsafe fn swap_out() -> bool {
+    if SWAP_SLOTS == 0 {
+        return false;
+    }
+    // the accessed bits cleared during the first turn are checked by the second one
+    let mut budget = 2 * candidate_pages() + 1;
+    while budget > 0 {
+        budget -= 1;
+        let (task, addr) = match clock_next() {
+            Some(page) => page,
+            None => return false
+        };
+        let pd = &mut TASKS[task].pd;
+        let entry = pd.get_entry(addr);
+        if entry & 0x1 == 0 {
+            continue;
+        }
+        if entry & PAGE_ACCESSED != 0 {
+            pd.set_entry(addr, entry &! PAGE_ACCESSED);
+            continue;
+        }
+        let phys_addr = entry &! 0xfff;
+        let slot = if entry & PAGE_DIRTY == 0 {
+            ZERO_SLOT
+        } else {
+            match alloc_slot() {
+                Some(slot) => slot,
+                None => return false
+            }
+        };
+        if slot != ZERO_SLOT {
+            write_slot(slot, phys_addr);
+        }
+        pd.set_entry(addr, (slot as Entry) << 12 | PAGE_SWAPPED);
+        frame_free(phys_addr);
+        SWAPPED_OUT += 1;
+        return true;
+    }
+    return false;
+}
+
+// Private memory of the tasks, file and shared memory pages are never swapped
+fn is_swappable(kind: RegionKind) -> bool {
+    match kind {
+        RegionKind::Code | RegionKind::Data | RegionKind::Stack | RegionKind::Heap | RegionKind::Anonymous => true,
+        _ => false
+    }
+}
+
+unsafe fn candidate_pages() -> usize {
+    let mut cnt = 0;
+    for task in 0..TASKS_NB {
+        if TASKS[task].state != TaskState::Free {
+            for region in ADDRESS_SPACES[task].regions.iter() {
+                if is_swappable(region.kind) {
+                    cnt += (region.end - region.start) as usize / FRAME_SIZE;
+                }
+            }
+        }
+    }
+    return cnt;
+}
+
+// Moves the clock hand to the next page of a swappable region
+unsafe fn clock_next() -> Option<(usize, u32)> {
+    for _ in 0..(TASKS_NB * MAX_REGIONS + 1) {
+        let region = ADDRESS_SPACES[HAND_TASK].regions[HAND_REGION];
+        if TASKS[HAND_TASK].state != TaskState::Free && is_swappable(region.kind) && HAND_ADDR < region.end {
+            let addr = max(HAND_ADDR, region.start);
+            HAND_ADDR = addr + FRAME_SIZE as u32;
+            return Some((HAND_TASK, addr));
+        }
+        HAND_ADDR = 0;
+        HAND_REGION += 1;
+        if HAND_REGION == MAX_REGIONS {
+            HAND_REGION = 0;
+            HAND_TASK = (HAND_TASK + 1) % TASKS_NB;
+        }
+    }
+    return None;
+}
+
+unsafe fn alloc_slot() -> Option<u32> {
+    for slot in 0..SWAP_SLOTS {
+        if SLOT_BITMAP[slot / 32] & (1 << (slot % 32)) == 0 {
+            SLOT_BITMAP[slot / 32] |= 1 << (slot % 32);
+            SWAP_USED += 1;
+            return Some(slot as u32);
+        }
+    }
+    return None;
+}
+
+unsafe fn release_slot(slot: u32) {
+    let slot = slot as usize;
+    if SLOT_BITMAP[slot / 32] & (1 << (slot % 32)) != 0 {
+        SLOT_BITMAP[slot / 32] &= !(1 << (slot % 32));
+        SWAP_USED -= 1;
+    }
+}
+
+unsafe fn write_slot(slot: u32, phys_addr: PhysAddr) {
+    let page = frame_map(phys_addr) as *mut u16;
+    for i in 0..SECTORS_PER_SLOT {
+        write_sector_to(IDE_SLAVE, slot * SECTORS_PER_SLOT + i, page.offset((i as usize * SECTOR_SIZE / 2) as isize));
+    }
+}
+
+unsafe fn read_slot(slot: u32, phys_addr: PhysAddr) {
+    let page = frame_map(phys_addr) as *mut u16;
+    for i in 0..SECTORS_PER_SLOT {
+        read_sector_from(IDE_SLAVE, slot * SECTORS_PER_SLOT + i, page.offset((i as usize * SECTOR_SIZE / 2) as isize));
+    }
+}
\ No newline at end of file
@@ -1,32 +1,76 @@
 #![allow(dead_code)]
 
+use core::mem::size_of;
+use core::cmp::min;
 use vga::*;
 use pio::*;
 use timer::*;
 use keyboard::*;
 use fs::*;
+use dev::dev_lookup;
+use proc::proc_lookup;
 use task::*;
-use paging::FRAME_SIZE;
+use paging::{FRAME_SIZE, KERNEL_BASE, USER_PD, PAGE_USER_FRAME};
 use kheap::*;
+use vm::*;
+use uaccess::*;
+use trace::trace_syscall;
+use x86::*;
 use common::*;
 
 extern "C" {
     pub fn _syscall_handler();
+    fn _sysenter_handler();
+}
+
+static mut SYSENTER_ENABLED: bool = false;
+
+/// Sets up the MSRs of the sysenter entry point if the processor supports it.
+/// int 48 remains available in any case.
+pub fn sysenter_init() -> bool {
+    if !has_sysenter() {
+        return false;
+    }
+    // sysenter loads ss with the selector following cs, sysexit the user
+    // code and data selectors located 16 and 24 bytes after cs
+    wrmsr(MSR_SYSENTER_CS, GDT_KERNEL_CODE_SELECTOR as u64);
+    wrmsr(MSR_SYSENTER_EIP, _sysenter_handler as *const () as u64);
+    unsafe {
+        let esp = &INITIAL_TSS_KERNEL_STACK as *const _ as u32 + STACK_SIZE as u32;
+        wrmsr(MSR_SYSENTER_ESP, esp as u64);
+        SYSENTER_ENABLED = true;
+    }
+    true
+}
+
+/// Sets the kernel stack used by sysenter, which must be the one of the
+/// running task as the cpu does not take it from the TSS
+pub fn sysenter_set_stack(esp: u32) {
+    unsafe {
+        if SYSENTER_ENABLED {
+            wrmsr(MSR_SYSENTER_ESP, esp as u64);
+        }
+    }
 }
 
 /// System call handler: call the appropriate system call according to the nb argument.
+/// Errors are returned as negative codes, unknown numbers give Invalid.
 /// Called by the assembly code _syscall_handler
 #[no_mangle]
-pub unsafe extern fn syscall_handler(nb: Syscall, _arg1: u32, _arg2: u32, _arg3: u32, _arg4: u32) -> i32 {
+pub unsafe extern fn syscall_handler(nb: u32, _arg1: u32, _arg2: u32, _arg3: u32, _arg4: u32) -> i32 {
+    let nb = match Syscall::from_u32(nb) {
+        Some(nb) => nb,
+        None => return result_to_code(Err(Error::Invalid))
+    };
     let addr = 0;
-    match nb {
-        Syscall::Puts => syscall_puts(addr, _arg1),
+    let result = match nb {
+        Syscall::Puts => syscall_puts(addr + _arg1, _arg2),
         Syscall::Putc => syscall_putc(_arg1),
-        Syscall::Exec => syscall_exec(addr, _arg1),
+        Syscall::Exec => syscall_exec(addr + _arg1, _arg2, _arg3),
         Syscall::Keypressed => syscall_keypressed(),
         Syscall::Getc => syscall_getc(),
-        Syscall::FileStat => syscall_file_stat(addr, _arg1, addr + _arg2),
-        Syscall::FileOpen => syscall_file_open(addr, _arg1),
+        Syscall::FileStat => syscall_file_stat(addr + _arg1, _arg2, addr + _arg3),
+        Syscall::FileOpen => syscall_file_open(addr + _arg1, _arg2),
         Syscall::FileClose => syscall_file_close(_arg1),
         Syscall::FileRead => syscall_file_read(_arg1, addr + _arg2, _arg3),
         Syscall::FileSeek => syscall_file_seek(_arg1, _arg2),
@@ -40,116 +84,207 @@ pub unsafe extern fn syscall_handler(nb: Syscall, _arg1: u32, _arg2: u32, _arg3:
         Syscall::CopyScr => syscall_copy_scr(addr + _arg1),
         Syscall::AllocFrame => syscall_alloc_frame(),
         Syscall::FreeFrame => syscall_free_frame(_arg1),
+        Syscall::FileWrite => syscall_file_write(_arg1, addr + _arg2, _arg3),
+        Syscall::FileIoctl => syscall_file_ioctl(_arg1, _arg2, _arg3),
+        Syscall::Brk => syscall_brk(_arg1),
+        Syscall::Sbrk => syscall_sbrk(_arg1),
+        Syscall::Mmap => syscall_mmap(addr + _arg1),
+        Syscall::Munmap => syscall_munmap(_arg1, _arg2),
+        Syscall::Mprotect => syscall_mprotect(_arg1, _arg2, _arg3),
+        Syscall::Msync => syscall_msync(_arg1, _arg2),
+        Syscall::ShmOpen => syscall_shm_open(addr + _arg1, _arg2, _arg3),
+    };
+    let code = result_to_code(result);
+    let task = current_task();
+    if task != -1 && TASKS[task as usize].trace {
+        trace_syscall(task as usize, nb, [_arg1, _arg2, _arg3, _arg4], code);
     }
+    code
 }
 
-unsafe fn syscall_puts(base_addr: u32, string_offset: u32) -> i32 {
-    let mut string = *((base_addr + string_offset) as *mut String);
-    string.offset(base_addr);
-    vga_write_str(string.to_string());
-    return 0;
+// The bytes are written as they are, by chunks so that any length works
+unsafe fn syscall_puts(str_addr: u32, len: u32) -> Result<u32, Error> {
+    if !access_ok(str_addr, len as usize, false) {
+        return Err(Error::Fault);
+    }
+    let mut buf = [0;MAX_STR_LEN];
+    let mut pos = 0;
+    while pos < len {
+        let cnt = min(len - pos, MAX_STR_LEN as u32);
+        copy_from_user(&mut buf[0], str_addr + pos, cnt as usize)?;
+        for &byte in &buf[0..cnt as usize] {
+            vga_write_byte(byte);
+        }
+        pos += cnt;
+    }
+    Ok(0)
 }
 
-unsafe fn syscall_putc(c: u32) -> i32 {
+unsafe fn syscall_putc(c: u32) -> Result<u32, Error> {
     vga_write_byte(c as u8);
-    return 0;
+    Ok(0)
 }
 
-unsafe fn syscall_exec(base_addr: u32, string_offset: u32) -> i32 {
-    let mut string = *((base_addr + string_offset) as *mut String);
-    string.offset(base_addr);
-    exec(string.to_string()) as i32
+unsafe fn syscall_exec(str_addr: u32, len: u32, flags: u32) -> Result<u32, Error> {
+    let mut buf = [0;MAX_STR_LEN];
+    exec(user_str(str_addr, len, &mut buf)?, flags & EXEC_TRACE != 0)?;
+    Ok(0)
 }
 
-unsafe fn syscall_keypressed() -> i32 {
-    keypressed() as i32
+unsafe fn syscall_keypressed() -> Result<u32, Error> {
+    Ok(keypressed() as u32)
 }
 
-unsafe fn syscall_getc() -> i32 {
-    getc() as i32
+unsafe fn syscall_getc() -> Result<u32, Error> {
+    Ok(getc() as u32)
 }
 
-unsafe fn syscall_file_stat(base_addr: u32, string_offset: u32, stat_addr: u32) -> i32 {
-    let mut string = *((base_addr + string_offset) as *mut String);
-    string.offset(base_addr);
-    let stat = stat_addr as *mut Stat;
-	*stat = Stat::new(string.to_string());
-    if (*stat).start == 0 {
-        return -1;
+unsafe fn syscall_file_stat(str_addr: u32, len: u32, stat_addr: u32) -> Result<u32, Error> {
+    let mut buf = [0;MAX_STR_LEN];
+    let filename = user_str(str_addr, len, &mut buf)?;
+    let stat = Stat::new(filename);
+    write_user(stat_addr, &stat)?;
+    if stat.start == 0 && dev_lookup(filename).is_none() && proc_lookup(filename).is_none() {
+        return Err(Error::NotFound);
     }
-    return 0;
+    Ok(0)
+}
+
+unsafe fn syscall_file_open(str_addr: u32, len: u32) -> Result<u32, Error> {
+    let mut buf = [0;MAX_STR_LEN];
+    let fd = file_open(user_str(str_addr, len, &mut buf)?)?;
+    Ok(fd as u32)
+}
+
+unsafe fn syscall_shm_open(str_addr: u32, len: u32, size: u32) -> Result<u32, Error> {
+    let mut buf = [0;MAX_STR_LEN];
+    let fd = file_open_shm(user_str(str_addr, len, &mut buf)?, size as usize)?;
+    Ok(fd as u32)
 }
 
-unsafe fn syscall_file_open(base_addr: u32, string_offset: u32) -> i32 {
-    let mut string = *((base_addr + string_offset) as *mut String);
-    string.offset(base_addr);
-    file_open(string.to_string())
+unsafe fn syscall_file_close(fd: u32) -> Result<u32, Error> {
+    file_close(fd as i32)?;
+    Ok(0)
 }
 
-unsafe fn syscall_file_close(fd: u32) -> i32 {
-    file_close(fd as i32)
+// Buffers are used in place, once checked
+unsafe fn syscall_file_read(fd: u32, buf_addr: u32, n: u32) -> Result<u32, Error> {
+    if !access_ok(buf_addr, n as usize, true) {
+        return Err(Error::Fault);
+    }
+    file_read(fd as i32, buf_addr as *mut u8, n as usize).map(|cnt| cnt as u32)
 }
 
-unsafe fn syscall_file_read(fd: u32, buf_addr: u32, n: u32) -> i32 {
-    file_read(fd as i32, buf_addr as *mut u8, n as usize)
+unsafe fn syscall_file_write(fd: u32, buf_addr: u32, n: u32) -> Result<u32, Error> {
+    if !access_ok(buf_addr, n as usize, false) {
+        return Err(Error::Fault);
+    }
+    file_write(fd as i32, buf_addr as *const u8, n as usize).map(|cnt| cnt as u32)
 }
 
-unsafe fn syscall_file_seek(fd: u32, offset: u32) -> i32 {
-    file_seek(fd as i32, offset as usize)
+unsafe fn syscall_file_ioctl(fd: u32, request: u32, arg: u32) -> Result<u32, Error> {
+    file_ioctl(fd as i32, request, arg)
 }
 
-unsafe fn syscall_file_iterator(it_addr: u32) -> i32 {
-    let it = it_addr as *mut FileIterator;
-    *it = FileIterator::new();
-    return 0;
+unsafe fn syscall_file_seek(fd: u32, offset: u32) -> Result<u32, Error> {
+    file_seek(fd as i32, offset as usize)?;
+    Ok(0)
 }
 
-unsafe fn syscall_file_next(base_addr: u32, string_offset: u32, it_addr: u32) -> i32 {
-    let bytes = (base_addr + string_offset) as *mut u8;
-    let it = it_addr as *mut FileIterator;
-    (*it).next(bytes) as i32
+unsafe fn syscall_file_iterator(it_addr: u32) -> Result<u32, Error> {
+    write_user(it_addr, &FileIterator::new())?;
+    Ok(0)
 }
 
-unsafe fn syscall_get_ticks() -> i32 {
-    get_ticks() as i32
+// NotFound once all the files have been listed
+unsafe fn syscall_file_next(base_addr: u32, string_offset: u32, it_addr: u32) -> Result<u32, Error> {
+    let mut it = read_user::<FileIterator>(it_addr)?;
+    let mut filename = [0;MAX_FILENAME_LENGTH];
+    if it.next(&mut filename[0]) == -1 {
+        return Err(Error::NotFound);
+    }
+    copy_to_user(base_addr + string_offset, &filename[0], MAX_FILENAME_LENGTH)?;
+    write_user(it_addr, &it)?;
+    Ok(0)
 }
 
-unsafe fn syscall_sleep(ms: u32) -> i32 {
+unsafe fn syscall_get_ticks() -> Result<u32, Error> {
+    Ok(get_ticks())
+}
+
+unsafe fn syscall_sleep(ms: u32) -> Result<u32, Error> {
     sleep(ms);
-    return 0;
+    Ok(0)
 }
 
-unsafe fn syscall_set_cursor(x: u32, y: u32) -> i32 {
+unsafe fn syscall_set_cursor(x: u32, y: u32) -> Result<u32, Error> {
     vga_set_cursor(x as usize, y as usize);
-    return 0;
+    Ok(0)
 }
 
-unsafe fn syscall_get_cursor(x_addr: u32, y_addr: u32) -> i32 {
+unsafe fn syscall_get_cursor(x_addr: u32, y_addr: u32) -> Result<u32, Error> {
     let cursor = vga_get_cursor();
-    *(x_addr as *mut u32) = cursor.0 as u32;
-    *(y_addr as *mut u32) = cursor.1 as u32;
-    return 0;
+    write_user(x_addr, &(cursor.0 as u32))?;
+    write_user(y_addr, &(cursor.1 as u32))?;
+    Ok(0)
 }
 
-unsafe fn syscall_cursor_disable(cd: u32) -> i32 {
+unsafe fn syscall_cursor_disable(cd: u32) -> Result<u32, Error> {
     if cd == 0 {
         enable_cursor();
     } else {
         disable_cursor();
     }
-    return 0;
+    Ok(0)
 }
 
-unsafe fn syscall_copy_scr(scr_addr: u32) -> i32 {
+unsafe fn syscall_copy_scr(scr_addr: u32) -> Result<u32, Error> {
+    if !access_ok(scr_addr, size_of::<FrameBuffer>(), false) {
+        return Err(Error::Fault);
+    }
     vga_copy_scr(scr_addr as *const FrameBuffer);
-    return 0;
+    Ok(0)
+}
+
+unsafe fn syscall_alloc_frame() -> Result<u32, Error> {
+    let addr = umalloc(FRAME_SIZE).ok_or(Error::NoMemory)?;
+    let entry = (*USER_PD).get_entry(addr);
+    (*USER_PD).set_entry(addr, entry | PAGE_USER_FRAME);
+    Ok(addr)
+}
+
+// Only the frames given by AllocFrame can be freed, not the pages of the regions
+unsafe fn syscall_free_frame(addr: u32) -> Result<u32, Error> {
+    if addr % FRAME_SIZE as u32 != 0 || addr >= KERNEL_BASE || (*USER_PD).get_entry(addr) & PAGE_USER_FRAME == 0 {
+        return Err(Error::Invalid);
+    }
+    ufree(addr, FRAME_SIZE);
+    Ok(0)
+}
+
+unsafe fn syscall_brk(addr: u32) -> Result<u32, Error> {
+    vm_brk(current_task() as usize, addr)
+}
+
+unsafe fn syscall_sbrk(incr: u32) -> Result<u32, Error> {
+    vm_sbrk(current_task() as usize, incr as i32)
+}
+
+unsafe fn syscall_mmap(args_addr: u32) -> Result<u32, Error> {
+    vm_mmap(current_task() as usize, &read_user::<MmapArgs>(args_addr)?)
+}
+
+unsafe fn syscall_munmap(addr: u32, len: u32) -> Result<u32, Error> {
+    vm_munmap(current_task() as usize, addr, len as usize)?;
+    Ok(0)
 }
 
-unsafe fn syscall_alloc_frame() -> i32 {
-    umalloc(FRAME_SIZE) as i32
+unsafe fn syscall_mprotect(addr: u32, len: u32, prot: u32) -> Result<u32, Error> {
+    vm_mprotect(current_task() as usize, addr, len as usize, prot)?;
+    Ok(0)
 }
 
-unsafe fn syscall_free_frame(addr: u32) -> i32 {
-    ufree(addr);
-    return 0;
+unsafe fn syscall_msync(addr: u32, len: u32) -> Result<u32, Error> {
+    vm_msync(current_task() as usize, addr, len as usize)?;
+    Ok(0)
 }
\ No newline at end of file
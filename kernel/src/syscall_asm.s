@@ -6,6 +6,7 @@ align 4                            ; the code must be 4 byte aligned
 extern syscall_handler
 
 global _syscall_handler
+global _sysenter_handler
 
 _syscall_handler:
     ; Save all registers
@@ -68,3 +69,50 @@ _syscall_handler:
     pop     ebx
     iret
 
+; Entry point of the system calls made with sysenter. The cpu loads cs, ss and
+; esp from the MSRs and disables interrupts, but saves nothing: the user stub
+; passes its stack pointer in ecx and its return address in edx, which sysexit
+; needs to get back. ecx and edx being taken, the arguments are in eax (nb),
+; ebx, edi, ebp and esi.
+_sysenter_handler:
+    push    ecx
+    push    edx
+    push    ds
+    push    es
+    push    fs
+    push    gs
+
+    ; Load kernel data descriptor into all segments
+    push    eax
+    mov     ax,GDT_KERNEL_DATA_SELECTOR
+    mov     ds,ax
+    mov     es,ax
+    mov     fs,ax
+    mov     gs,ax
+    pop     eax
+
+    ; the syscall is interruptible, as with the trap gate of int 48
+    sti
+
+    ; same arguments as for _syscall_handler
+    str     ecx
+    push    ecx
+    push    esi
+    push    ebp
+    push    edi
+    push    ebx
+    push    eax
+
+    call    syscall_handler
+
+    ; ebx, esi, edi and ebp are preserved by syscall_handler
+    add     esp,24
+
+    pop     gs
+    pop     fs
+    pop     es
+    pop     ds
+    pop     edx
+    pop     ecx
+    sysexit
+
@@ -7,14 +7,22 @@ use paging::*;
 use fs::*;
 use vga::*;
 use kheap::*;
+use vm::*;
+use syscall::sysenter_set_stack;
+use trace::trace_clear;
 use common::*;
 
-pub const TASKS_NB: usize = 8; 
+pub const TASKS_NB: usize = MAX_TASKS;
 pub const STACK_SIZE: usize = 0x10000;
 
 pub static mut INITIAL_TSS: Tss = Tss::new();
 pub static mut INITIAL_TSS_KERNEL_STACK: [u8;STACK_SIZE] = [0;STACK_SIZE];
 pub static mut TASKS: [Task;TASKS_NB] = [Task::new();TASKS_NB];
+// Task run by the double fault exception, with its own stack so that it
+// works when the fault comes from an overflow of a kernel stack
+static mut DOUBLE_FAULT_TSS: Tss = Tss::new();
+static mut DOUBLE_FAULT_STACK: [u8;FRAME_SIZE] = [0;FRAME_SIZE];
+static mut CURRENT_TASK: i8 = -1;
 
 #[derive(Clone, Copy)]
 #[repr(C)]
@@ -22,10 +30,20 @@ pub struct Task {
     pub tss: Tss,
     pub tss_selector: u16,
     pub kernel_stack: [u8;STACK_SIZE],
-    pub is_free: bool,
+    pub state: TaskState,
+    pub parent: i8,
+    pub trace: bool,
+    pub name: [u8;MAX_FILENAME_LENGTH],
     pub pd: PageDirectory
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskState {
+    Free,
+    Running,
+    Waiting
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
 pub struct Tss {
@@ -53,80 +71,149 @@ pub struct Tss {
 extern "C" {
     fn task_ltr(tss_selector: u16);
     fn task_switch(tss_selector: u16);
+    fn task_exit() -> !;
 }
 
 pub fn tasks_init() {
     unsafe {
         INITIAL_TSS.ss0 = GDT_KERNEL_DATA_SELECTOR as u16;
         INITIAL_TSS.esp0 = &INITIAL_TSS_KERNEL_STACK as *const _ as u32 + STACK_SIZE as u32;
-        INITIAL_TSS.cr3 = phys!(INITIAL_PD.tables as u32);
+        INITIAL_TSS.cr3 = INITIAL_PD.cr3();
         GDT[5] = GdtEntry::make_tss(&INITIAL_TSS as *const _ as u32, DPL_KERNEL);
         task_ltr(GDT[5].to_selector() as u16);
         
         for task in &mut TASKS {
             task.setup();
+            // shared by all the directories as the other kernel tables
+            if !INITIAL_PD.unmap_kernel_page(task.stack_guard()) {
+                println!("tasks: no page table for the stack guard pages");
+            }
         }
     }
 }
 
-pub fn exec(filename: &str) -> i8 {
+/// Sets up the task of the double fault exception, which runs handler with
+/// interrupts disabled. Returns the selector of its TSS for the task gate.
+pub fn double_fault_task_init(handler: u32) -> u16 {
+    unsafe {
+        DOUBLE_FAULT_TSS.eip = handler;
+        DOUBLE_FAULT_TSS.esp = &DOUBLE_FAULT_STACK as *const _ as u32 + FRAME_SIZE as u32;
+        DOUBLE_FAULT_TSS.ebp = DOUBLE_FAULT_TSS.esp;
+        DOUBLE_FAULT_TSS.cs = GDT_KERNEL_CODE_SELECTOR as u16;
+        DOUBLE_FAULT_TSS.ds = GDT_KERNEL_DATA_SELECTOR as u16;
+        DOUBLE_FAULT_TSS.es = DOUBLE_FAULT_TSS.ds;
+        DOUBLE_FAULT_TSS.fs = DOUBLE_FAULT_TSS.ds;
+        DOUBLE_FAULT_TSS.gs = DOUBLE_FAULT_TSS.ds;
+        DOUBLE_FAULT_TSS.ss = DOUBLE_FAULT_TSS.ds;
+        DOUBLE_FAULT_TSS.eflags = 0x2;  // reserved bit 1 is always set
+        DOUBLE_FAULT_TSS.cr3 = INITIAL_PD.cr3();
+        GDT[6] = GdtEntry::make_tss(&DOUBLE_FAULT_TSS as *const _ as u32, DPL_KERNEL);
+        GDT[6].to_selector() as u16
+    }
+}
+
+/// Returns the task whose kernel stack guard page holds addr
+pub fn stack_guard_task(addr: u32) -> Option<usize> {
+    unsafe { TASKS.iter().position(|task| addr &! 0xfff == task.stack_guard()) }
+}
+
+/// Runs the program filename until it ends. If trace is set, its system calls
+/// are logged from an empty trace, as are those of the programs it runs.
+pub fn exec(filename: &str, trace: bool) -> Result<(), Error> {
     let idx = free_task();
-    if idx != -1 {
-        unsafe {
-            let fd = file_open(filename);
-            if fd != -1 {
-                let stat = Stat::new(filename);
-                if file_type(fd) != TYPE_EXEC {
-                    println!("exec: {}: not an executable", filename);
-                    return -1;
-                }
-                // Create new directory using initial directory 
-                let pd_backup = if get_cr3() != phys!(INITIAL_PD.tables as u32) {
-                    switch_directory(&mut INITIAL_PD);
-                    USER_PD
-                } else {
-                    &mut INITIAL_PD as *mut PageDirectory
-                };
-                TASKS[idx as usize].pd = INITIAL_PD.new_directory();
-                switch_directory(&mut TASKS[idx as usize].pd);
-                
-                // Alloc frames starting at address 0
-                // Additional frames are allocated for the stack
-                let code_addr = umalloc(stat.size);
-                let stack_addr = umalloc(STACK_SIZE);
-                file_read(fd, code_addr as *mut u8, stat.size);
-                
-                // Setup task with page directory previously allocated
-                TASKS[idx as usize].is_free = false;
-                TASKS[idx as usize].tss.eip = 0;
-                TASKS[idx as usize].tss.esp = stack_addr + STACK_SIZE as u32;
-                TASKS[idx as usize].tss.ebp = stack_addr + STACK_SIZE as u32;
-                TASKS[idx as usize].tss.cr3 = phys!(TASKS[idx as usize].pd.tables as u32);
-                
-                task_switch(TASKS[idx as usize].tss_selector as u16);
-                switch_directory(&mut INITIAL_PD);
-                // re-load original directory and free memory
-                TASKS[idx as usize].is_free = true;
-                ufree(code_addr);
-                ufree(stack_addr);
-                switch_directory(pd_backup);
-                TASKS[idx as usize].pd.free();
-                return 0;
-            } else {
-                println!("exec: {}: not found", filename);
-            }
+    if idx == -1 {
+        return Err(Error::NoTask);
+    }
+    unsafe {
+        let fd = file_open(filename)?;
+        let stat = Stat::new(filename);
+        let mut header = ExecHeader::null();
+        if file_type(fd) == TYPE_EXEC {
+            file_read(fd, &mut header as *mut _ as *mut u8, size_of::<ExecHeader>()).ok();
+            file_seek(fd, 0).ok();
+        }
+        if !header.is_valid(stat.size) {
+            file_close(fd).ok();
+            return Err(Error::NotExecutable);
+        }
+        // Create new directory using initial directory 
+        let pd_backup = if get_cr3() != INITIAL_PD.cr3() {
+            switch_directory(&mut INITIAL_PD);
+            USER_PD
+        } else {
+            &mut INITIAL_PD as *mut PageDirectory
+        };
+        TASKS[idx as usize].pd = INITIAL_PD.new_directory();
+        switch_directory(&mut TASKS[idx as usize].pd);
+        
+        // Load the program at USER_CODE_START, the stack is mapped below USER_STACK_TOP
+        if !vm_init(idx as usize, &header, fd, stat.size) {
+            // the directory is freed with the frames that could be allocated
+            switch_directory(pd_backup);
+            TASKS[idx as usize].pd.free();
+            file_close(fd).ok();
+            return Err(Error::NoMemory);
+        }
+        file_close(fd).ok();
+        
+        // Setup task with page directory previously allocated
+        TASKS[idx as usize].set_name(filename);
+        TASKS[idx as usize].parent = CURRENT_TASK;
+        TASKS[idx as usize].trace = trace || (CURRENT_TASK != -1 && TASKS[CURRENT_TASK as usize].trace);
+        if trace {
+            trace_clear();
+        }
+        TASKS[idx as usize].state = TaskState::Running;
+        if CURRENT_TASK != -1 {
+            TASKS[CURRENT_TASK as usize].state = TaskState::Waiting;
         }
-    } else {
-        println!("exec: no free task slot found");
+        CURRENT_TASK = idx;
+        TASKS[idx as usize].tss.eip = header.entry;
+        TASKS[idx as usize].tss.esp = USER_STACK_TOP;
+        TASKS[idx as usize].tss.ebp = USER_STACK_TOP;
+        TASKS[idx as usize].tss.cr3 = TASKS[idx as usize].pd.cr3();
+        
+        sysenter_set_stack(TASKS[idx as usize].tss.esp0);
+        task_switch(TASKS[idx as usize].tss_selector as u16);
+        // the TSS holds the state of the task at its end
+        TASKS[idx as usize].setup();
+        // the shared file pages are written back with the directory of the task
+        switch_directory(&mut TASKS[idx as usize].pd);
+        vm_release(idx as usize);
+        switch_directory(&mut INITIAL_PD);
+        // re-load original directory and free memory
+        CURRENT_TASK = TASKS[idx as usize].parent;
+        if CURRENT_TASK != -1 {
+            TASKS[CURRENT_TASK as usize].state = TaskState::Running;
+            sysenter_set_stack(TASKS[CURRENT_TASK as usize].tss.esp0);
+        } else {
+            sysenter_set_stack(INITIAL_TSS.esp0);
+        }
+        TASKS[idx as usize].state = TaskState::Free;
+        file_close_all(idx);
+        // frames mapped outside of the regions, e.g. by AllocFrame, go with the directory
+        switch_directory(pd_backup);
+        TASKS[idx as usize].pd.free();
+        return Ok(());
     }
-    return -1;
+}
+
+/// Ends the running task after a fatal exception. Execution goes on in exec,
+/// which frees the task as if it had returned.
+pub fn task_abort() -> ! {
+    unsafe { task_exit() }
+}
+
+/// Returns the index of the running task, -1 for the kernel
+pub fn current_task() -> i8 {
+    unsafe { CURRENT_TASK }
 }
 
 fn free_task() -> i8 {
     unsafe {
         let mut cnt = 0;
         for task in TASKS.iter() {
-            if task.is_free {
+            if task.state == TaskState::Free {
                 return cnt;
             }
             cnt += 1;
@@ -141,11 +228,27 @@ impl Task {
             tss: Tss::new(),
             tss_selector: 0,
             kernel_stack: [0;STACK_SIZE],
-            is_free: true,
+            state: TaskState::Free,
+            parent: -1,
+            trace: false,
+            name: [0;MAX_FILENAME_LENGTH],
             pd: PageDirectory::null()
         }
     }
     
+    /// First page of the kernel stack, left unmapped so that an overflow
+    /// faults instead of overwriting the TSS of the task
+    pub fn stack_guard(&self) -> u32 {
+        let bottom = &self.kernel_stack as *const _ as u32;
+        (bottom + FRAME_SIZE as u32 - 1) &! (FRAME_SIZE as u32 - 1)
+    }
+    
+    fn set_name(&mut self, filename: &str) {
+        let len = if filename.len() < MAX_FILENAME_LENGTH { filename.len() } else { MAX_FILENAME_LENGTH };
+        self.name = [0;MAX_FILENAME_LENGTH];
+        self.name[0..len].copy_from_slice(&filename.as_bytes()[0..len]);
+    }
+    
     unsafe fn setup(&mut self) {
         let idx = ((self as *mut _ as usize) - (&TASKS as *const _ as usize)) / size_of::<Task>();
         // Add the task's TSS to the GDT
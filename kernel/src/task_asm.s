@@ -1,5 +1,6 @@
 global task_ltr
 global task_switch
+global task_exit
 
 section .data
 tss_sel_offs dd 0  ; must always be 0
@@ -31,3 +32,13 @@ task_switch:
     mov     [ecx],ax
     call    far [ecx-4]
     ret
+
+; Return to the task that called the current one. The state of the current
+; task is saved in its TSS but it is never resumed.
+;
+; void task_exit()
+task_exit:
+    pushfd
+    or      dword [esp],1<<14  ; set the NT flag so that iret returns to the previous task
+    popfd
+    iret
@@ -0,0 +1,128 @@
+//! Trace of the system calls made by the traced tasks. Each call is logged
+//! with its decoded arguments and its result in a ring buffer that is read
+//! through /proc/trace.
+#![allow(dead_code)]
+
+use core::fmt::{self, Write};
+use core::cmp::min;
+use core::ascii::escape_default;
+use uaccess::copy_from_user;
+use common::*;
+
+const TRACE_BUFFER_SIZE: usize = 8192;
+// Longest string argument logged, longer ones are cut
+const TRACE_STR_LEN: usize = 40;
+
+static mut TRACE: TraceBuffer = TraceBuffer::new();
+
+/// Ring buffer keeping the last TRACE_BUFFER_SIZE bytes written
+struct TraceBuffer {
+    data: [u8;TRACE_BUFFER_SIZE],
+    written: usize
+}
+
+/// Empties the trace, done when a traced program is started
+pub fn trace_clear() {
+    unsafe { TRACE.written = 0; }
+}
+
+/// Size in bytes of the trace
+pub fn trace_size() -> usize {
+    unsafe { min(TRACE.written, TRACE_BUFFER_SIZE) }
+}
+
+/// Reads at most n bytes of the trace starting at the position pos,
+/// the oldest bytes being at position 0
+pub fn trace_read(pos: usize, buf: *mut u8, n: usize) -> usize {
+    let size = trace_size();
+    if pos >= size {
+        return 0;
+    }
+    let cnt = min(n, size - pos);
+    unsafe {
+        let start = TRACE.written - size + pos;
+        for i in 0..cnt {
+            *buf.offset(i as isize) = TRACE.data[(start + i) % TRACE_BUFFER_SIZE];
+        }
+    }
+    return cnt;
+}
+
+/// Logs the system call nb made by the task with the arguments args and
+/// the value code it returned
+pub fn trace_syscall(task: usize, nb: Syscall, args: [u32;4], code: i32) {
+    let trace = unsafe { &mut TRACE };
+    write!(trace, "[{}] {:?}(", task, nb).ok();
+    write_args(trace, nb, args).ok();
+    match code_to_result(code) {
+        Ok(_) => writeln!(trace, ") = {}", code),
+        Err(err) => writeln!(trace, ") = {} {}", code, err)
+    }.ok();
+}
+
+fn write_args(trace: &mut TraceBuffer, nb: Syscall, args: [u32;4]) -> fmt::Result {
+    match nb {
+        Syscall::Puts | Syscall::FileOpen => write_str_arg(trace, args[0], args[1]),
+        Syscall::Exec | Syscall::FileStat => {
+            write_str_arg(trace, args[0], args[1])?;
+            write!(trace, ", {:#x}", args[2])
+        }
+        Syscall::ShmOpen => {
+            write_str_arg(trace, args[0], args[1])?;
+            write!(trace, ", {}", args[2])
+        }
+        Syscall::Putc => write!(trace, "'{}'", (args[0] as u8 as char).escape_default()),
+        Syscall::FileClose | Syscall::Sleep | Syscall::CursorDisable => write!(trace, "{}", args[0]),
+        Syscall::FileRead | Syscall::FileWrite => write!(trace, "{}, {:#x}, {}", args[0], args[1], args[2]),
+        Syscall::FileSeek | Syscall::SetCursor => write!(trace, "{}, {}", args[0], args[1]),
+        Syscall::FileIoctl => write!(trace, "{}, {:#x}, {:#x}", args[0], args[1], args[2]),
+        Syscall::Sbrk => write!(trace, "{}", args[0] as i32),
+        Syscall::Munmap | Syscall::Msync => write!(trace, "{:#x}, {}", args[0], args[1]),
+        Syscall::Mprotect => write!(trace, "{:#x}, {}, {:#x}", args[0], args[1], args[2]),
+        Syscall::FileIterator | Syscall::CopyScr | Syscall::FreeFrame | Syscall::Brk | Syscall::Mmap => {
+            write!(trace, "{:#x}", args[0])
+        }
+        Syscall::GetCursor | Syscall::FileNext => write!(trace, "{:#x}, {:#x}", args[0], args[1]),
+        Syscall::Keypressed | Syscall::Getc | Syscall::GetTicks | Syscall::AllocFrame => Ok(())
+    }
+}
+
+// Strings are quoted and their bytes escaped so that the trace remains ASCII,
+// a bad one is shown as its address and length
+fn write_str_arg(trace: &mut TraceBuffer, addr: u32, len: u32) -> fmt::Result {
+    let mut buf = [0;TRACE_STR_LEN];
+    let cnt = min(len as usize, TRACE_STR_LEN);
+    if copy_from_user(&mut buf[0], addr, cnt).is_err() {
+        return write!(trace, "{:#x}, {}", addr, len);
+    }
+    trace.write_char('"')?;
+    for &byte in &buf[0..cnt] {
+        for c in escape_default(byte) {
+            trace.write_char(c as char)?;
+        }
+    }
+    trace.write_char('"')?;
+    if len as usize > TRACE_STR_LEN {
+        trace.write_str("...")?;
+    }
+    Ok(())
+}
+
+impl TraceBuffer {
+    const fn new() -> TraceBuffer {
+        TraceBuffer {
+            data: [0;TRACE_BUFFER_SIZE],
+            written: 0
+        }
+    }
+}
+
+impl Write for TraceBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.data[self.written % TRACE_BUFFER_SIZE] = byte;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,73 @@
+//! Access to the memory of the running task from the system calls.
+//! User addresses are checked against the page directory of the task before
+//! the kernel reads or writes them, so that a bad pointer makes the system
+//! call fail instead of touching the kernel memory.
+#![allow(dead_code)]
+
+use core::mem::size_of;
+use core::str::from_utf8;
+use rlibc::memcpy;
+use paging::*;
+use task::current_task;
+use vm::vm_page_ok;
+use common::*;
+
+/// Checks that the n bytes at addr belong to the running task, and are
+/// writable if write is set
+pub fn access_ok(addr: u32, n: usize, write: bool) -> bool {
+    let task = current_task();
+    if task == -1 || addr >= KERNEL_BASE || n > (KERNEL_BASE - addr) as usize {
+        return false;
+    }
+    let end = addr + n as u32;
+    let mut page = addr &! 0xfff;
+    while page < end {
+        if !vm_page_ok(task as usize, page, write) {
+            return false;
+        }
+        page += FRAME_SIZE as u32;
+    }
+    return true;
+}
+
+/// Copies n bytes of the running task at src to dst
+pub fn copy_from_user(dst: *mut u8, src: u32, n: usize) -> Result<(), Error> {
+    if !access_ok(src, n, false) {
+        return Err(Error::Fault);
+    }
+    unsafe { memcpy(dst, src as *const u8, n); }
+    return Ok(());
+}
+
+/// Copies n bytes at src to dst in the running task
+pub fn copy_to_user(dst: u32, src: *const u8, n: usize) -> Result<(), Error> {
+    if !access_ok(dst, n, true) {
+        return Err(Error::Fault);
+    }
+    unsafe { memcpy(dst as *mut u8, src, n); }
+    return Ok(());
+}
+
+/// Reads a value of the running task at addr
+pub fn read_user<T: Copy>(addr: u32) -> Result<T, Error> {
+    if !access_ok(addr, size_of::<T>(), false) {
+        return Err(Error::Fault);
+    }
+    unsafe { Ok(*(addr as *const T)) }
+}
+
+/// Writes a value at addr in the running task
+pub fn write_user<T: Copy>(addr: u32, value: &T) -> Result<(), Error> {
+    copy_to_user(addr, value as *const T as *const u8, size_of::<T>())
+}
+
+/// Copies the len bytes of the running task at addr into buf and returns them
+/// as a string, which must be valid UTF-8 and fit in buf
+pub fn user_str(addr: u32, len: u32, buf: &mut [u8]) -> Result<&str, Error> {
+    let len = len as usize;
+    if len > buf.len() {
+        return Err(Error::NameTooLong);
+    }
+    copy_from_user(buf.as_mut_ptr(), addr, len)?;
+    from_utf8(&buf[0..len]).map_err(|_| Error::Invalid)
+}
\ No newline at end of file
@@ -0,0 +1,81 @@
+//! Allocator of the virtual pages of the area where umalloc maps its frames.
+//! The area is split in blocks of 2^order pages like the frames of the buddy
+//! allocator. Each node of a complete binary tree holds the largest order of
+//! the free blocks below it plus one, 0 if it has none, so blocks are found
+//! and given back by walking a single path of the tree.
+#![allow(dead_code)]
+
+use core::mem::size_of;
+use kheap::*;
+
+/// The area holds 2^AREA_ORDER pages
+pub const AREA_ORDER: usize = 14;
+const NODES_NB: usize = 2 << AREA_ORDER;
+
+pub struct AreaTree {
+    nodes: [u8;NODES_NB]
+}
+
+impl AreaTree {
+    /// Allocates the tree of an area whose pages are all free, null if the
+    /// heap is full
+    pub fn new() -> *mut AreaTree {
+        let tree = kmalloc(size_of::<AreaTree>()) as *mut AreaTree;
+        if !tree.is_null() {
+            unsafe {
+                for idx in 1..NODES_NB {
+                    (*tree).nodes[idx] = (AREA_ORDER - level(idx) + 1) as u8;
+                }
+            }
+        }
+        return tree;
+    }
+
+    /// Index of the first page of a free block of 2^order pages, None if
+    /// there is no such block
+    pub fn alloc(&mut self, order: usize) -> Option<usize> {
+        if order > AREA_ORDER || (self.nodes[1] as usize) < order + 1 {
+            return None;
+        }
+        let mut idx = 1;
+        for _ in order..AREA_ORDER {
+            idx *= 2;
+            if (self.nodes[idx] as usize) < order + 1 {
+                idx += 1;
+            }
+        }
+        self.nodes[idx] = 0;
+        self.update(idx);
+        return Some((idx - (1 << (AREA_ORDER - order))) << order);
+    }
+
+    /// Gives back the block of 2^order pages starting at the page index page
+    pub fn free(&mut self, page: usize, order: usize) {
+        let idx = (1 << (AREA_ORDER - order)) + (page >> order);
+        self.nodes[idx] = (order + 1) as u8;
+        self.update(idx);
+    }
+
+    // Recomputes the ancestors of the node idx, two free buddies make a
+    // free block of the upper order
+    fn update(&mut self, idx: usize) {
+        let mut idx = idx;
+        while idx > 1 {
+            idx /= 2;
+            let full = (AREA_ORDER - level(idx)) as u8;
+            let (left, right) = (self.nodes[2 * idx], self.nodes[2 * idx + 1]);
+            self.nodes[idx] = if left == full && right == full {
+                full + 1
+            } else if left > right {
+                left
+            } else {
+                right
+            };
+        }
+    }
+}
+
+// Depth of the node idx, the root is at depth 0
+fn level(idx: usize) -> usize {
+    (size_of::<usize>() * 8 - 1) - idx.leading_zeros() as usize
+}
\ No newline at end of file
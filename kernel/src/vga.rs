@@ -84,6 +84,10 @@ pub fn vga_copy_scr(scr: *const FrameBuffer) {
     }
 }
 
+pub fn vga_raw_buffer() -> *mut u8 {
+    unsafe { SCREEN.buffer as *mut u8 }
+}
+
 impl Write for Screen {
     fn write_str(&mut self, s: &str) -> Result<(), Error> {
         self.write_str(s);
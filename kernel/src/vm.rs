@@ -0,0 +1,698 @@
+//! Regions of the user address spaces.
+//! Each task has a read-only code region, a data region and a stack region set
+//! up by exec, the stack growing down on the page faults below it up to
+//! USER_STACK_MAX bytes, a heap region
+//! that brk and sbrk move from USER_HEAP_START, and anonymous regions
+//! created by mmap between USER_MMAP_START and USER_MMAP_END. Regions are
+//! page aligned and their frames are mapped as soon as they are created,
+//! except for file mappings whose pages are read from the disk on the first
+//! access. Dirty pages of shared file mappings are written back by msync,
+//! munmap and at the end of the task. Each task has its own copy of the
+//! pages, so shared mappings of the same file are not kept coherent.
+//! Mappings of shared memory objects use the frames of the object instead.
+#![allow(dead_code)]
+
+use core::cmp::{min, max};
+use rlibc::memset;
+use paging::*;
+use frame::*;
+use kheap::*;
+use task::*;
+use fs::*;
+use shm::*;
+use swap::*;
+use vga::*;
+use common::*;
+
+pub const MAX_REGIONS: usize = 32;
+
+pub static mut ADDRESS_SPACES: [AddressSpace;TASKS_NB] = [AddressSpace::new();TASKS_NB];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionKind {
+    Free,
+    Code,
+    Data,
+    Stack,
+    Heap,
+    Anonymous,
+    File,
+    Shared
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub start: u32,
+    pub end: u32,
+    pub prot: u32,
+    pub kind: RegionKind,
+    pub file: Option<FileMapping>,
+    pub shm: Option<ShmMapping>
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FileMapping {
+    pub stat: Stat,
+    pub offset: u32,    // position in the file of the start of the region
+    pub shared: bool
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShmMapping {
+    pub id: usize,
+    pub offset: u32     // position in the object of the start of the region
+}
+
+#[derive(Clone, Copy)]
+pub struct AddressSpace {
+    pub regions: [Region;MAX_REGIONS],
+    pub brk: u32
+}
+
+/// Loads the program of size bytes of the file fd described by header, maps the
+/// initial part of the stack and registers the regions of the task. The code
+/// becomes read-only once loaded. The directory of the task must be the current one.
+pub fn vm_init(task: usize, header: &ExecHeader, fd: i32, size: usize) -> bool {
+    unsafe {
+        let space = &mut ADDRESS_SPACES[task];
+        let pd = &mut TASKS[task].pd;
+        *space = AddressSpace::new();
+        let code = Region::new(USER_CODE_START, header.text_end, PROT_READ | PROT_EXEC, RegionKind::Code);
+        let data = Region::new(header.text_end, page_align(header.end as usize), PROT_READ | PROT_WRITE, RegionKind::Data);
+        let stack = Region::new(USER_STACK_TOP - page_align(USER_STACK_SIZE), USER_STACK_TOP, PROT_READ | PROT_WRITE, RegionKind::Stack);
+        if !map_range(pd, code.start, data.end, PROT_READ | PROT_WRITE) || !map_range(pd, stack.start, stack.end, stack.prot) {
+            return false;
+        }
+        file_read(fd, code.start as *mut u8, size).ok();
+        let mut page = code.start;
+        while page < code.end {
+            let entry = pd.get_entry(page);
+            pd.set_entry(page, (entry &! 0xfff) | (entry & PAGE_DIRTY) | page_flags(code.prot));
+            page += FRAME_SIZE as u32;
+        }
+        space.insert(code);
+        if data.start < data.end {
+            space.insert(data);
+        }
+        space.insert(stack);
+        space.insert(Region::new(USER_HEAP_START, USER_HEAP_START, PROT_READ | PROT_WRITE, RegionKind::Heap));
+        space.brk = USER_HEAP_START;
+        return true;
+    }
+}
+
+/// Unmaps all the regions of the task, including the swapped pages. The directory
+/// of the task must be the current one so that the shared file pages can be written back.
+pub fn vm_release(task: usize) {
+    unsafe {
+        let space = &mut ADDRESS_SPACES[task];
+        let pd = &mut TASKS[task].pd;
+        for region in space.regions.iter() {
+            if region.kind != RegionKind::Free {
+                drop_region(pd, region);
+            }
+        }
+        *space = AddressSpace::new();
+    }
+}
+
+/// Moves the program break of the running task to addr, which must be in the
+/// heap area. Returns the new break.
+pub fn vm_brk(task: usize, addr: u32) -> Result<u32, Error> {
+    unsafe {
+        let space = &mut ADDRESS_SPACES[task];
+        let pd = &mut TASKS[task].pd;
+        let heap = match space.regions.iter().position(|region| region.kind == RegionKind::Heap) {
+            Some(heap) => heap,
+            None => return Err(Error::Invalid)
+        };
+        if addr < USER_HEAP_START || addr > USER_MMAP_START {
+            return Err(Error::Invalid);
+        }
+        let old_end = space.regions[heap].end;
+        let new_end = page_align(addr as usize);
+        if new_end > old_end {
+            if !range_is_free(pd, old_end, new_end) || !map_range(pd, old_end, new_end, space.regions[heap].prot) {
+                return Err(Error::NoMemory);
+            }
+        } else if new_end < old_end {
+            unmap_range(pd, new_end, old_end);
+        }
+        space.regions[heap].end = new_end;
+        space.brk = addr;
+        return Ok(addr);
+    }
+}
+
+/// Moves the program break of the running task by incr bytes.
+/// Returns the previous break.
+pub fn vm_sbrk(task: usize, incr: i32) -> Result<u32, Error> {
+    let old_brk = unsafe { ADDRESS_SPACES[task].brk };
+    let new_brk = old_brk as i64 + incr as i64;
+    if new_brk < 0 || new_brk > USER_MMAP_START as i64 {
+        return Err(Error::Invalid);
+    }
+    vm_brk(task, new_brk as u32)?;
+    return Ok(old_brk);
+}
+
+/// Maps len bytes of zeroed memory in the running task, or without MAP_ANONYMOUS
+/// the content of the file or of the shared memory object fd from offset. The region is placed at addr with
+/// MAP_FIXED, replacing the previous mappings, and anywhere in the mapping area
+/// otherwise. Returns the address of the region.
+pub fn vm_mmap(task: usize, args: &MmapArgs) -> Result<u32, Error> {
+    let (addr, len, prot, flags) = (args.addr, args.len, args.prot, args.flags);
+    if len == 0 || len > (USER_MMAP_END - USER_MMAP_START) as usize {
+        return Err(Error::Invalid);
+    }
+    let size = page_align(len);
+    let shm = if flags & MAP_ANONYMOUS == 0 { file_shm(args.fd) } else { None };
+    if let Some(id) = shm {
+        if args.offset % FRAME_SIZE as u32 != 0 || args.offset as usize + size as usize > shm_size(id) {
+            return Err(Error::Invalid);
+        }
+    }
+    let file = if flags & MAP_ANONYMOUS == 0 && shm.is_none() {
+        match file_disk_stat(args.fd) {
+            // changes of the shared mappings are written back to the file
+            Some(ref stat) if flags & MAP_SHARED != 0 && prot & PROT_WRITE != 0 && !file_writable(stat) => {
+                return Err(Error::ReadOnly);
+            }
+            Some(stat) if args.offset % FRAME_SIZE as u32 == 0 => Some(FileMapping {
+                stat: stat,
+                offset: args.offset,
+                shared: flags & MAP_SHARED != 0
+            }),
+            Some(_) => return Err(Error::Invalid),
+            None => return Err(Error::BadFd)
+        }
+    } else {
+        None
+    };
+    unsafe {
+        let start = if flags & MAP_FIXED != 0 {
+            if addr % FRAME_SIZE as u32 != 0 || addr < USER_MMAP_START || addr > USER_MMAP_END - size {
+                return Err(Error::Invalid);
+            }
+            vm_munmap(task, addr, len)?;
+            addr
+        } else {
+            match find_area(&ADDRESS_SPACES[task], &mut TASKS[task].pd, size) {
+                Some(start) => start,
+                None => return Err(Error::NoMemory)
+            }
+        };
+        let space = &mut ADDRESS_SPACES[task];
+        let pd = &mut TASKS[task].pd;
+        let mut region = Region::new(start, start + size, prot, RegionKind::Anonymous);
+        if file.is_some() {
+            region.kind = RegionKind::File;
+            region.file = file;
+        }
+        if let Some(id) = shm {
+            region.kind = RegionKind::Shared;
+            region.shm = Some(ShmMapping { id: id, offset: args.offset });
+        }
+        if !range_is_free(pd, region.start, region.end) || !space.insert(region) {
+            return Err(Error::NoMemory);
+        }
+        let mapped = match region.kind {
+            RegionKind::Anonymous => map_range(pd, region.start, region.end, prot),
+            RegionKind::Shared => {
+                shm_ref(region.shm.unwrap().id);
+                map_shared(pd, &region)
+            }
+            // file pages are loaded by vm_fault
+            _ => true
+        };
+        if !mapped {
+            space.remove(region.start);
+            return Err(Error::NoMemory);
+        }
+        return Ok(start);
+    }
+}
+
+/// Unmaps the regions created by mmap in the running task between addr and
+/// addr + len. Regions partially in the range are split.
+pub fn vm_munmap(task: usize, addr: u32, len: usize) -> Result<(), Error> {
+    if addr % FRAME_SIZE as u32 != 0 || len == 0 || addr < USER_MMAP_START || len > (USER_MMAP_END - addr) as usize {
+        return Err(Error::Invalid);
+    }
+    let end = addr + page_align(len);
+    unsafe {
+        let space = &mut ADDRESS_SPACES[task];
+        let pd = &mut TASKS[task].pd;
+        if !space.split(addr) || !space.split(end) {
+            return Err(Error::NoMemory);
+        }
+        for region in space.regions.iter_mut() {
+            if region.is_mapping() && region.start >= addr && region.end <= end {
+                drop_region(pd, region);
+                *region = Region::null();
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Changes the protection of the pages of the running task between addr and
+/// addr + len. The range must be covered by regions created by mmap. Shared
+/// mappings of files that cannot be written cannot be made writable.
+pub fn vm_mprotect(task: usize, addr: u32, len: usize, prot: u32) -> Result<(), Error> {
+    if addr % FRAME_SIZE as u32 != 0 || len == 0 || addr < USER_MMAP_START || len > (USER_MMAP_END - addr) as usize {
+        return Err(Error::Invalid);
+    }
+    let end = addr + page_align(len);
+    unsafe {
+        let space = &mut ADDRESS_SPACES[task];
+        let pd = &mut TASKS[task].pd;
+        let mut covered = addr;
+        while covered < end {
+            match space.find(covered) {
+                Some(idx) if space.regions[idx].is_mapping() => {
+                    if prot & PROT_WRITE != 0 && !region_writable(&space.regions[idx]) {
+                        return Err(Error::ReadOnly);
+                    }
+                    covered = space.regions[idx].end;
+                }
+                _ => return Err(Error::Invalid)
+            }
+        }
+        if !space.split(addr) || !space.split(end) {
+            return Err(Error::NoMemory);
+        }
+        for region in space.regions.iter_mut() {
+            if region.is_mapping() && region.start >= addr && region.end <= end {
+                region.prot = prot;
+                let mut page = region.start;
+                while page < region.end {
+                    // file pages not loaded yet and swapped pages get the protection in vm_fault
+                    let entry = pd.get_entry(page);
+                    if entry &! 0xfff != 0 && entry & PAGE_SWAPPED == 0 {
+                        pd.set_entry(page, (entry &! 0xfff) | (entry & PAGE_DIRTY) | page_flags(prot));
+                    }
+                    page += FRAME_SIZE as u32;
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Writes the dirty pages of the shared file mappings of the running task
+/// between addr and addr + len back to the disk
+pub fn vm_msync(task: usize, addr: u32, len: usize) -> Result<(), Error> {
+    if addr % FRAME_SIZE as u32 != 0 || addr < USER_MMAP_START || len > (USER_MMAP_END - addr) as usize {
+        return Err(Error::Invalid);
+    }
+    let end = addr + page_align(len);
+    unsafe {
+        let space = &ADDRESS_SPACES[task];
+        let pd = &mut TASKS[task].pd;
+        for region in space.regions.iter() {
+            if region.kind == RegionKind::File && region.start < end && addr < region.end {
+                sync_range(pd, region, max(region.start, addr), min(region.end, end));
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Page fault handler: reads back a swapped page, loads the page of a file mapping
+/// or grows the stack to the address addr. Returns false if the fault is an invalid
+/// access of the running task.
+pub fn vm_fault(addr: u32, error_code: u32) -> bool {
+    let task = current_task();
+    // present pages are only faulted by protection violations
+    if task == -1 || addr >= KERNEL_BASE || error_code & 0x1 != 0 {
+        return false;
+    }
+    unsafe {
+        let space = &ADDRESS_SPACES[task as usize];
+        let pd = &mut TASKS[task as usize].pd;
+        let region = match space.find(addr) {
+            Some(idx) => space.regions[idx],
+            None => return grow_stack(task as usize, addr)
+        };
+        if region.prot == PROT_NONE || (error_code & 0x2 != 0 && region.prot & PROT_WRITE == 0) {
+            return false;
+        }
+        let page = addr &! 0xfff;
+        let entry = pd.get_entry(page);
+        if entry & PAGE_SWAPPED != 0 {
+            if !swap_in(pd, page, entry, page_flags(region.prot)) {
+                println!("vm: out of memory");
+                return false;
+            }
+            return true;
+        }
+        let file = match region.file {
+            Some(file) => file,
+            None => return false
+        };
+        let phys_addr = swap_frame_alloc();
+        if phys_addr == 0 || !ensure_table(pd, page) {
+            println!("vm: out of memory");
+            if phys_addr != 0 {
+                frame_free(phys_addr);
+            }
+            return false;
+        }
+        pd.map_page(page, phys_addr, USER_MODE);
+        memset(page as *mut u8, 0, FRAME_SIZE);
+        let pos = file.offset + page - region.start;
+        file_read_at(&file.stat, pos as usize, page as *mut u8, FRAME_SIZE);
+        // the page is clean until the task writes to it
+        pd.set_entry(page, phys_addr | page_flags(region.prot));
+        return true;
+    }
+}
+
+/// Tells whether the task can access the page holding addr, directly or through
+/// the page fault handler. Used to check the pointers given to the system calls.
+pub fn vm_page_ok(task: usize, addr: u32, write: bool) -> bool {
+    unsafe {
+        let pd = &mut TASKS[task].pd;
+        let entry = pd.get_entry(addr &! 0xfff);
+        if entry & 0x1 != 0 {
+            return entry & USER_MODE != 0 && (!write || entry & 0x2 != 0);
+        }
+        let space = &ADDRESS_SPACES[task];
+        match space.find(addr) {
+            Some(idx) => {
+                let region = space.regions[idx];
+                region.prot != PROT_NONE && (!write || region.prot & PROT_WRITE != 0) &&
+                    (entry & PAGE_SWAPPED != 0 || region.file.is_some())
+            }
+            None => stack_can_grow(space, addr)
+        }
+    }
+}
+
+// Tells whether addr is below the stack, in the area where it can grow
+fn stack_can_grow(space: &AddressSpace, addr: u32) -> bool {
+    let limit = USER_STACK_TOP - USER_STACK_MAX as u32;
+    match space.regions.iter().find(|region| region.kind == RegionKind::Stack) {
+        Some(stack) => addr >= limit && addr < stack.start,
+        None => false
+    }
+}
+
+// Extends the stack of the task down to the page holding addr. Accesses to
+// the guard page below the largest stack are reported as overflows.
+unsafe fn grow_stack(task: usize, addr: u32) -> bool {
+    let space = &mut ADDRESS_SPACES[task];
+    let pd = &mut TASKS[task].pd;
+    if !stack_can_grow(space, addr) {
+        let limit = USER_STACK_TOP - USER_STACK_MAX as u32;
+        if addr < limit && addr >= limit - FRAME_SIZE as u32 {
+            println!("vm: stack overflow");
+        }
+        return false;
+    }
+    let stack = space.regions.iter().position(|region| region.kind == RegionKind::Stack).unwrap();
+    let page = addr &! 0xfff;
+    let start = space.regions[stack].start;
+    // pages mapped outside of any region, e.g. by AllocFrame, stop the stack
+    if !range_is_free(pd, page, start) {
+        println!("vm: stack overflow");
+        return false;
+    }
+    if !map_range(pd, page, start, space.regions[stack].prot) {
+        return false;
+    }
+    space.regions[stack].start = page;
+    return true;
+}
+
+pub fn page_align(size: usize) -> u32 {
+    ((size + FRAME_SIZE - 1) & !(FRAME_SIZE - 1)) as u32
+}
+
+// Flags of the page table entries of a region, pages without access are not present
+fn page_flags(prot: u32) -> Entry {
+    let mut flags = USER_MODE;
+    if prot != PROT_NONE {
+        flags |= 0x1;
+    }
+    if prot & PROT_WRITE != 0 {
+        flags |= 0x2;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PAGE_NX;
+    }
+    return flags;
+}
+
+// First address of the mapping area where size bytes are free
+fn find_area(space: &AddressSpace, pd: &mut PageDirectory, size: u32) -> Option<u32> {
+    let mut addr = USER_MMAP_START;
+    while addr <= USER_MMAP_END - size {
+        let end = addr + size;
+        match space.regions.iter().find(|region| region.kind != RegionKind::Free && region.start < end && addr < region.end) {
+            Some(region) => addr = region.end,
+            None => {
+                // pages mapped outside of any region, e.g. by AllocFrame
+                let mut page = addr;
+                while page < end && pd.mmap_frame_state(page / FRAME_SIZE as u32) == 0 {
+                    page += FRAME_SIZE as u32;
+                }
+                if page == end {
+                    return Some(addr);
+                }
+                addr = page + FRAME_SIZE as u32;
+            }
+        }
+    }
+    return None;
+}
+
+fn range_is_free(pd: &mut PageDirectory, start: u32, end: u32) -> bool {
+    let mut page = start;
+    while page < end {
+        if pd.mmap_frame_state(page / FRAME_SIZE as u32) != 0 {
+            return false;
+        }
+        page += FRAME_SIZE as u32;
+    }
+    return true;
+}
+
+// Maps zeroed frames between start and end, the directory must be the current one
+unsafe fn map_range(pd: &mut PageDirectory, start: u32, end: u32, prot: u32) -> bool {
+    let mut addr = start;
+    while addr < end {
+        let phys_addr = swap_frame_alloc();
+        if phys_addr == 0 || !ensure_table(pd, addr) {
+            println!("vm: out of memory");
+            if phys_addr != 0 {
+                frame_free(phys_addr);
+            }
+            unmap_range(pd, start, addr);
+            return false;
+        }
+        // pages are writable while being cleared
+        pd.map_page(addr, phys_addr, USER_MODE);
+        memset(addr as *mut u8, 0, FRAME_SIZE);
+        pd.set_entry(addr, phys_addr | page_flags(prot));
+        addr += FRAME_SIZE as u32;
+    }
+    return true;
+}
+
+// Unmaps the pages between start and end and gives their frames back,
+// including the pages made not present by PROT_NONE and the swapped ones
+unsafe fn unmap_range(pd: &mut PageDirectory, start: u32, end: u32) {
+    let mut addr = start;
+    while addr < end {
+        let entry = pd.get_entry(addr);
+        if entry != 0 {
+            pd.set_entry(addr, 0);
+            pd.mmap_reset_frame(addr / FRAME_SIZE as u32);
+            if entry & PAGE_SWAPPED != 0 {
+                swap_free(entry);
+            } else {
+                frame_free(entry &! 0xfff);
+            }
+        }
+        addr += FRAME_SIZE as u32;
+    }
+}
+
+// Maps the frames of the shared memory object of the region, the reference
+// on the object is dropped if they cannot be mapped
+unsafe fn map_shared(pd: &mut PageDirectory, region: &Region) -> bool {
+    let shm = region.shm.unwrap();
+    let mut addr = region.start;
+    while addr < region.end {
+        if !ensure_table(pd, addr) {
+            println!("vm: out of memory");
+            clear_range(pd, region.start, addr);
+            shm_unref(shm.id);
+            return false;
+        }
+        let phys_addr = shm_frame(shm.id, (shm.offset + addr - region.start) as usize);
+        pd.map_page(addr, phys_addr, USER_MODE);
+        pd.set_entry(addr, phys_addr | page_flags(region.prot));
+        addr += FRAME_SIZE as u32;
+    }
+    return true;
+}
+
+// Unmaps the pages of the heap or of a mapping and releases what they hold.
+// The directory must be the current one to write the shared file pages back.
+unsafe fn drop_region(pd: &mut PageDirectory, region: &Region) {
+    match region.shm {
+        Some(shm) => {
+            clear_range(pd, region.start, region.end);
+            shm_unref(shm.id);
+        }
+        None => {
+            sync_range(pd, region, region.start, region.end);
+            unmap_range(pd, region.start, region.end);
+        }
+    }
+}
+
+// Unmaps the pages between start and end without freeing their frames
+unsafe fn clear_range(pd: &mut PageDirectory, start: u32, end: u32) {
+    let mut addr = start;
+    while addr < end {
+        if pd.get_entry(addr) &! 0xfff != 0 {
+            pd.set_entry(addr, 0);
+            pd.mmap_reset_frame(addr / FRAME_SIZE as u32);
+        }
+        addr += FRAME_SIZE as u32;
+    }
+}
+
+// Writes the dirty pages of a shared file region between start and end back to
+// the file, the directory must be the current one
+unsafe fn sync_range(pd: &mut PageDirectory, region: &Region, start: u32, end: u32) {
+    let file = match region.file {
+        Some(file) if file.shared => file,
+        _ => return
+    };
+    let mut page = start;
+    while page < end {
+        let entry = pd.get_entry(page);
+        if entry & 0x1 != 0 && entry & PAGE_DIRTY != 0 {
+            let pos = file.offset + page - region.start;
+            // the part of the page past the end of the file is dropped
+            file_write_at(&file.stat, pos as usize, page as *const u8, FRAME_SIZE).ok();
+            pd.set_entry(page, entry &! PAGE_DIRTY);
+        }
+        page += FRAME_SIZE as u32;
+    }
+}
+
+// Whether the pages of the region can be made writable
+fn region_writable(region: &Region) -> bool {
+    match region.file {
+        Some(ref file) if file.shared => file_writable(&file.stat),
+        _ => true
+    }
+}
+
+// Creates the page table of the address addr if needed
+unsafe fn ensure_table(pd: &mut PageDirectory, addr: u32) -> bool {
+    let table_idx = addr as usize / TABLE_SIZE;
+    if pd[table_idx] & 0x1 != 0 {
+        return true;
+    }
+    let table_addr = kmalloc_page(FRAME_SIZE);
+    if table_addr == 0 {
+        return false;
+    }
+    memset(table_addr as *mut u8, 0, FRAME_SIZE);
+    pd.set_table(table_idx, table_addr, USER_MODE);
+    return true;
+}
+
+impl Region {
+    const fn null() -> Region {
+        Region {
+            start: 0,
+            end: 0,
+            prot: PROT_NONE,
+            kind: RegionKind::Free,
+            file: None,
+            shm: None
+        }
+    }
+
+    fn new(start: u32, end: u32, prot: u32, kind: RegionKind) -> Region {
+        Region {
+            start: start,
+            end: end,
+            prot: prot,
+            kind: kind,
+            file: None,
+            shm: None
+        }
+    }
+
+    /// Regions created by mmap
+    pub fn is_mapping(&self) -> bool {
+        self.kind == RegionKind::Anonymous || self.kind == RegionKind::File || self.kind == RegionKind::Shared
+    }
+}
+
+impl AddressSpace {
+    pub const fn new() -> AddressSpace {
+        AddressSpace {
+            regions: [Region::null();MAX_REGIONS],
+            brk: 0
+        }
+    }
+
+    /// Index of the region holding the address addr
+    pub fn find(&self, addr: u32) -> Option<usize> {
+        self.regions.iter().position(|region| region.kind != RegionKind::Free && region.start <= addr && addr < region.end)
+    }
+
+    fn insert(&mut self, region: Region) -> bool {
+        match self.regions.iter().position(|region| region.kind == RegionKind::Free) {
+            Some(idx) => {
+                self.regions[idx] = region;
+                true
+            }
+            None => false
+        }
+    }
+
+    fn remove(&mut self, start: u32) {
+        for region in self.regions.iter_mut() {
+            if region.kind != RegionKind::Free && region.start == start {
+                *region = Region::null();
+            }
+        }
+    }
+
+    // Cuts the mapping holding addr in two regions ending and starting at addr
+    fn split(&mut self, addr: u32) -> bool {
+        match self.find(addr) {
+            Some(idx) if self.regions[idx].is_mapping() && self.regions[idx].start != addr => {
+                let mut upper = self.regions[idx];
+                upper.start = addr;
+                if let Some(ref mut file) = upper.file {
+                    file.offset += addr - self.regions[idx].start;
+                }
+                if let Some(ref mut shm) = upper.shm {
+                    shm.offset += addr - self.regions[idx].start;
+                }
+                if !self.insert(upper) {
+                    return false;
+                }
+                self.regions[idx].end = addr;
+                // both halves hold a reference on the shared memory object
+                if let Some(shm) = upper.shm {
+                    shm_ref(shm.id);
+                }
+                true
+            }
+            _ => true
+        }
+    }
+}
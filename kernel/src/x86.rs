@@ -51,4 +51,80 @@ pub fn sti() {
 // External interrupts wake up the CPU, hence the cli instruction.
 pub fn halt() {
     unsafe { asm!("hlt"); }
+}
+
+// Interrupt flag in EFLAGS
+const EFLAGS_IF: u32 = 1 << 9;
+
+extern "C" {
+    fn get_eflags() -> u32;
+    fn get_return_address(depth: u32) -> u32;
+    fn get_cpuid_eax(leaf: u32) -> u32;
+    fn get_cpuid_edx(leaf: u32) -> u32;
+    fn read_msr(msr: u32) -> u64;
+    fn write_msr(msr: u32, low: u32, high: u32);
+}
+
+// Model specific registers used by sysenter
+pub const MSR_SYSENTER_CS: u32 = 0x174;
+pub const MSR_SYSENTER_ESP: u32 = 0x175;
+pub const MSR_SYSENTER_EIP: u32 = 0x176;
+// Extended feature enable register and its no-execute enable bit
+pub const MSR_EFER: u32 = 0xC0000080;
+pub const EFER_NXE: u64 = 1 << 11;
+
+// SEP flag of cpuid leaf 1 (edx): sysenter and sysexit are supported
+const CPUID_SEP: u32 = 1 << 11;
+
+/// Returns true if the processor supports the sysenter and sysexit instructions
+pub fn has_sysenter() -> bool {
+    unsafe { get_cpuid_edx(1) & CPUID_SEP != 0 }
+}
+
+// NX flag of cpuid leaf 0x80000001 (edx): the PAE entries have an execute disable bit
+const CPUID_NX: u32 = 1 << 20;
+
+/// Returns true if the processor supports the NX bit of the PAE page tables
+pub fn has_nx() -> bool {
+    unsafe { get_cpuid_eax(0x80000000) >= 0x80000001 && get_cpuid_edx(0x80000001) & CPUID_NX != 0 }
+}
+
+/// Reads the model specific register msr
+pub fn rdmsr(msr: u32) -> u64 {
+    unsafe { read_msr(msr) }
+}
+
+/// Writes value to the model specific register msr
+pub fn wrmsr(msr: u32, value: u64) {
+    unsafe { write_msr(msr, value as u32, (value >> 32) as u32); }
+}
+
+/// Returns the address the function depth frames above the caller returns to,
+/// found by following the saved frame pointers. It is always inlined so that
+/// depth 0 is the frame of the function calling it.
+#[inline(always)]
+pub fn return_address(depth: u32) -> u32 {
+    unsafe { get_return_address(depth) }
+}
+
+/// Disables hardware interrupts until it is dropped. Interrupts are only
+/// enabled again if they were enabled when the guard was created.
+pub struct InterruptGuard {
+    enabled: bool
+}
+
+impl InterruptGuard {
+    pub fn new() -> InterruptGuard {
+        let enabled = unsafe { get_eflags() } & EFLAGS_IF != 0;
+        cli();
+        InterruptGuard { enabled: enabled }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if self.enabled {
+            sti();
+        }
+    }
 }
\ No newline at end of file
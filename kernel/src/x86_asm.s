@@ -0,0 +1,88 @@
+global get_eflags
+global get_return_address
+global get_cpuid_eax
+global get_cpuid_edx
+global read_msr
+global write_msr
+
+section .text
+
+get_eflags:
+    push ebp
+    mov ebp, esp
+
+    pushfd
+    pop eax
+
+    leave
+    ret
+
+; u32 get_return_address(u32 depth)
+get_return_address:
+    push ebp
+    mov ebp, esp
+
+    mov ecx, [ebp+8]
+    mov eax, [ebp]      ; frame of the caller
+.up:
+    test ecx, ecx
+    jz .found
+    mov eax, [eax]
+    dec ecx
+    jmp .up
+.found:
+    mov eax, [eax+4]
+
+    leave
+    ret
+
+; u32 get_cpuid_eax(u32 leaf)
+get_cpuid_eax:
+    push ebp
+    mov ebp, esp
+    push ebx            ; cpuid overwrites ebx which must be preserved
+
+    mov eax, [ebp+8]
+    cpuid
+
+    pop ebx
+    leave
+    ret
+
+; u32 get_cpuid_edx(u32 leaf)
+get_cpuid_edx:
+    push ebp
+    mov ebp, esp
+    push ebx            ; cpuid overwrites ebx which must be preserved
+
+    mov eax, [ebp+8]
+    cpuid
+    mov eax, edx
+
+    pop ebx
+    leave
+    ret
+
+; u64 read_msr(u32 msr), returned in edx:eax
+read_msr:
+    push ebp
+    mov ebp, esp
+
+    mov ecx, [ebp+8]
+    rdmsr
+
+    leave
+    ret
+
+; void write_msr(u32 msr, u32 low, u32 high)
+write_msr:
+    push ebp
+    mov ebp, esp
+
+    mov ecx, [ebp+8]
+    mov eax, [ebp+12]
+    mov edx, [ebp+16]
+    wrmsr
+
+    leave
+    ret
\ No newline at end of file
@@ -0,0 +1,124 @@
+//! Checks and repairs a MicroFS image from the host:
+//! fsck <image> [-r]
+//! fsck <image> -j <blocks> reserves a journal of the given number of blocks
+//! fsck <image> -m creates the metadata table, or the missing records of the
+//! files added since the last call
+
+extern crate common;
+
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use common::{BlockDevice, FsGeometry, Journal, SECTOR_SIZE, fsck, journal_create, journal_replay};
+use common::{meta_create, meta_stamp};
+
+struct ImageFile {
+    file: File
+}
+
+impl BlockDevice for ImageFile {
+    fn read_sector(&mut self, sector: u32, buf: &mut [u8;SECTOR_SIZE]) {
+        self.file.seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE as u64)).expect("seek failed");
+        self.file.read_exact(buf).expect("read failed");
+    }
+    
+    fn write_sector(&mut self, sector: u32, buf: &[u8;SECTOR_SIZE]) {
+        self.file.seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE as u64)).expect("seek failed");
+        self.file.write_all(buf).expect("write failed");
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let journal_blocks = if args.len() == 4 && args[2] == "-j" { args[3].parse::<usize>().ok() } else { None };
+    let valid = match args.len() {
+        2 => true,
+        3 => args[2] == "-r" || args[2] == "-m",
+        4 => journal_blocks.is_some(),
+        _ => false
+    };
+    if !valid {
+        eprintln!("usage: {} <image> [-r | -m | -j <blocks>]", args[0]);
+        process::exit(2);
+    }
+    let repair = args.len() > 2;
+    let file = match OpenOptions::new().read(true).write(repair).open(&args[1]) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", args[1], e);
+            process::exit(2);
+        }
+    };
+    let mut image = ImageFile { file: file };
+    
+    let mut raw_sb = [0;SECTOR_SIZE];
+    image.read_sector(0, &mut raw_sb);
+    let mut geometry = match FsGeometry::from_superblock(&raw_sb) {
+        Some(geometry) => geometry,
+        None => {
+            eprintln!("{}: not a MicroFS image", args[1]);
+            process::exit(2);
+        }
+    };
+    
+    if let Some(blocks) = journal_blocks {
+        if geometry.has_journal() {
+            eprintln!("{}: already has a journal of {} sectors", args[1], geometry.journal_sectors);
+            process::exit(1);
+        }
+        if !journal_create(&mut image, &geometry, blocks) {
+            eprintln!("{}: not enough free blocks at the end of the image", args[1]);
+            process::exit(1);
+        }
+        println!("{}: journal of {} blocks created", args[1], blocks);
+        return;
+    }
+    
+    if args.len() == 3 && args[2] == "-m" {
+        if !geometry.has_meta() {
+            if !meta_create(&mut image, &geometry) {
+                eprintln!("{}: not enough free blocks for the metadata table", args[1]);
+                process::exit(1);
+            }
+            image.read_sector(0, &mut raw_sb);
+            geometry = FsGeometry::from_superblock(&raw_sb).unwrap_or(geometry);
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let created = meta_stamp(&mut image, &geometry, now as u32);
+        println!("{}: {} metadata records created", args[1], created);
+        return;
+    }
+    
+    // a committed transaction must be applied before looking at the metadata
+    if repair {
+        let replayed = journal_replay(&mut image, &geometry);
+        if replayed != 0 {
+            println!("journal: {} sectors replayed", replayed);
+        }
+    }
+    let (report, committed) = {
+        let mut journal = Journal::begin(&mut image, &geometry);
+        let report = fsck(&mut journal, &geometry, repair);
+        (report, journal.commit())
+    };
+    if committed.is_err() {
+        eprintln!("{}: the repair does not fit in the journal, image left unchanged", args[1]);
+        process::exit(1);
+    }
+    
+    println!("{} files, {} used blocks", report.files, report.used_blocks);
+    println!("lost blocks: {}", report.lost_blocks);
+    println!("cross-linked blocks: {}", report.cross_links);
+    println!("bad chains: {}", report.bad_chains);
+    println!("size mismatches: {}", report.size_mismatches);
+    if report.is_clean() {
+        println!("{}: clean", args[1]);
+    } else if report.repaired {
+        println!("{}: repaired", args[1]);
+    } else {
+        println!("{}: errors found, run with -r to repair", args[1]);
+        process::exit(1);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,302 @@
+//! Checks and repairs of small MicroFS images built in memory
+
+extern crate common;
+
+use common::*;
+
+// 64 blocks of one sector: the superblock, the FAT and the root directory,
+// then the blocks of the files
+const BLOCKS: usize = 64;
+const ROOT_ENTRY: usize = 2;
+
+struct MemDisk {
+    data: Vec<u8>
+}
+
+impl BlockDevice for MemDisk {
+    fn read_sector(&mut self, sector: u32, buf: &mut [u8;SECTOR_SIZE]) {
+        let start = sector as usize * SECTOR_SIZE;
+        buf.copy_from_slice(&self.data[start..start + SECTOR_SIZE]);
+    }
+
+    fn write_sector(&mut self, sector: u32, buf: &[u8;SECTOR_SIZE]) {
+        let start = sector as usize * SECTOR_SIZE;
+        self.data[start..start + SECTOR_SIZE].copy_from_slice(buf);
+    }
+}
+
+impl MemDisk {
+    fn new() -> MemDisk {
+        let mut disk = MemDisk { data: vec![0;BLOCKS * SECTOR_SIZE] };
+        disk.data[0x0d] = 1;
+        disk.data[0x24] = BLOCKS as u8;
+        disk.data[0x2c] = ROOT_ENTRY as u8;
+        for block in 0..(ROOT_ENTRY + 1) {
+            disk.set_fat(block, FAT_END);
+        }
+        disk
+    }
+
+    fn geometry(&self) -> FsGeometry {
+        FsGeometry::from_superblock(&self.data[0..SECTOR_SIZE]).unwrap()
+    }
+
+    fn fat(&self, block: usize) -> u8 {
+        self.data[FAT_START_SECTOR as usize * SECTOR_SIZE + block]
+    }
+
+    fn set_fat(&mut self, block: usize, next: u8) {
+        self.data[FAT_START_SECTOR as usize * SECTOR_SIZE + block] = next;
+    }
+
+    fn entry(&self, idx: usize) -> usize {
+        ROOT_ENTRY * SECTOR_SIZE + idx * ENTRY_SIZE
+    }
+
+    // Adds the file idx of the root directory, stored in the given chain of blocks
+    fn add_file(&mut self, idx: usize, name: &str, size: u32, chain: &[usize]) {
+        let entry = self.entry(idx);
+        self.data[entry..entry + name.len()].copy_from_slice(name.as_bytes());
+        let start = if chain.is_empty() { FAT_END as u16 } else { chain[0] as u16 };
+        self.set_entry(idx, start, size);
+        for (i, &block) in chain.iter().enumerate() {
+            let next = if i + 1 < chain.len() { chain[i + 1] as u8 } else { FAT_END };
+            self.set_fat(block, next);
+        }
+    }
+
+    fn set_entry(&mut self, idx: usize, start: u16, size: u32) {
+        let entry = self.entry(idx);
+        self.data[entry + ENTRY_START_OFFSET] = start as u8;
+        self.data[entry + ENTRY_START_OFFSET + 1] = (start >> 8) as u8;
+        for i in 0..4 {
+            self.data[entry + ENTRY_SIZE_OFFSET + i] = (size >> (8 * i)) as u8;
+        }
+    }
+
+    fn start(&self, idx: usize) -> usize {
+        let entry = self.entry(idx) + ENTRY_START_OFFSET;
+        self.data[entry] as usize | (self.data[entry + 1] as usize) << 8
+    }
+
+    fn size(&self, idx: usize) -> usize {
+        let entry = self.entry(idx) + ENTRY_SIZE_OFFSET;
+        (0..4).fold(0, |size, i| size | (self.data[entry + i] as usize) << (8 * i))
+    }
+
+    fn check(&mut self, repair: bool) -> FsckReport {
+        let geometry = self.geometry();
+        fsck(self, &geometry, repair)
+    }
+
+    // Repairs the image and checks that a second run finds nothing to repair
+    fn repair(&mut self) -> FsckReport {
+        let report = self.check(true);
+        assert!(report.repaired);
+        let again = self.check(true);
+        assert!(again.is_clean());
+        assert!(!again.repaired);
+        report
+    }
+}
+
+#[test]
+fn clean_image() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "hello", 700, &[3, 4]);
+    disk.add_file(1, "empty", 0, &[5]);
+    let report = disk.check(true);
+    assert!(report.is_clean());
+    assert!(!report.repaired);
+    assert_eq!(report.files, 2);
+    assert_eq!(report.used_blocks, 3);
+}
+
+#[test]
+fn check_without_repair_leaves_the_image() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "hello", 700, &[3, 4]);
+    disk.set_fat(10, FAT_END);
+    let before = disk.data.clone();
+    let report = disk.check(false);
+    assert_eq!(report.lost_blocks, 1);
+    assert!(!report.repaired);
+    assert!(disk.data == before);
+}
+
+#[test]
+fn lost_blocks_are_freed() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "hello", 700, &[3, 4]);
+    disk.set_fat(10, 11);
+    disk.set_fat(11, FAT_END);
+    let report = disk.repair();
+    assert_eq!(report.lost_blocks, 2);
+    assert_eq!(disk.fat(10), FAT_FREE);
+    assert_eq!(disk.fat(11), FAT_FREE);
+    assert_eq!(disk.fat(4), FAT_END);
+}
+
+#[test]
+fn bad_chain_is_cut() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "hello", 3 * SECTOR_SIZE as u32, &[3, 4, 5]);
+    // the second block points to a free block
+    disk.set_fat(5, FAT_FREE);
+    let report = disk.repair();
+    assert_eq!(report.bad_chains, 1);
+    assert_eq!(report.size_mismatches, 1);
+    assert_eq!(disk.fat(4), FAT_END);
+    assert_eq!(disk.size(0), 2 * SECTOR_SIZE);
+}
+
+#[test]
+fn bad_first_block_empties_the_file() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "hello", 700, &[3, 4]);
+    disk.set_entry(0, BLOCKS as u16 + 1, 700);
+    let report = disk.repair();
+    assert_eq!(report.bad_chains, 1);
+    assert_eq!(report.lost_blocks, 2);
+    assert_eq!(disk.start(0), FAT_END as usize);
+    assert_eq!(disk.size(0), 0);
+}
+
+#[test]
+fn cross_link_is_cut() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "first", 2 * SECTOR_SIZE as u32, &[3, 4]);
+    disk.add_file(1, "second", 2 * SECTOR_SIZE as u32, &[5, 4]);
+    let report = disk.repair();
+    assert_eq!(report.cross_links, 1);
+    assert_eq!(disk.fat(3), 4);
+    assert_eq!(disk.fat(5), FAT_END);
+    assert_eq!(disk.size(1), SECTOR_SIZE);
+}
+
+#[test]
+fn cross_linked_first_block_empties_the_file() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "first", 2 * SECTOR_SIZE as u32, &[3, 4]);
+    disk.add_file(1, "second", SECTOR_SIZE as u32, &[]);
+    disk.set_entry(1, 3, SECTOR_SIZE as u32);
+    let report = disk.repair();
+    assert_eq!(report.cross_links, 1);
+    assert_eq!(disk.start(1), FAT_END as usize);
+    assert_eq!(disk.size(1), 0);
+    assert_eq!(disk.size(0), 2 * SECTOR_SIZE);
+}
+
+#[test]
+fn size_larger_than_the_chain_is_clamped() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "hello", 5 * SECTOR_SIZE as u32, &[3, 4]);
+    let report = disk.repair();
+    assert_eq!(report.size_mismatches, 1);
+    assert_eq!(disk.size(0), 2 * SECTOR_SIZE);
+}
+
+#[test]
+fn chain_longer_than_the_size_is_trimmed() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "hello", 10, &[3, 4, 5]);
+    let report = disk.repair();
+    assert_eq!(report.size_mismatches, 1);
+    assert_eq!(report.used_blocks, 1);
+    assert_eq!(disk.fat(3), FAT_END);
+    assert_eq!(disk.fat(4), FAT_FREE);
+    assert_eq!(disk.fat(5), FAT_FREE);
+    assert_eq!(disk.size(0), 10);
+}
+
+#[test]
+fn empty_file_stays_clean() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "empty", 0, &[]);
+    let report = disk.check(true);
+    assert!(report.is_clean());
+    assert!(!report.repaired);
+}
+
+#[test]
+fn journal_and_metadata_blocks_are_not_lost() {
+    let mut disk = MemDisk::new();
+    disk.add_file(0, "hello", 700, &[3, 4]);
+    let geometry = disk.geometry();
+    assert!(journal_create(&mut disk, &geometry, 5));
+    let geometry = disk.geometry();
+    assert!(geometry.has_journal());
+    assert!(meta_create(&mut disk, &geometry));
+    assert!(disk.geometry().has_meta());
+    let report = disk.check(true);
+    assert!(report.is_clean());
+    assert_eq!(report.used_blocks, 2);
+}
+
+#[test]
+fn journal_too_small_for_the_metadata_is_refused() {
+    let mut disk = MemDisk::new();
+    let geometry = disk.geometry();
+    assert!(!journal_create(&mut disk, &geometry, 2));
+    assert!(!disk.geometry().has_journal());
+}
+
+#[test]
+fn transaction_reaches_the_disk_at_the_commit() {
+    let mut disk = MemDisk::new();
+    let geometry = disk.geometry();
+    assert!(journal_create(&mut disk, &geometry, 5));
+    let geometry = disk.geometry();
+    let sector = [0xaa;SECTOR_SIZE];
+    {
+        let mut journal = Journal::begin(&mut disk, &geometry);
+        journal.write_sector(10, &sector);
+        let mut read = [0;SECTOR_SIZE];
+        journal.read_sector(10, &mut read);
+        assert!(read[..] == sector[..]);
+        assert!(journal.commit().is_ok());
+    }
+    assert!(disk.data[10 * SECTOR_SIZE..11 * SECTOR_SIZE] == sector[..]);
+    // nothing is left to replay
+    assert_eq!(journal_replay(&mut disk, &geometry), 0);
+}
+
+#[test]
+fn transaction_larger_than_the_journal_is_dropped() {
+    let mut disk = MemDisk::new();
+    let geometry = disk.geometry();
+    assert!(journal_create(&mut disk, &geometry, 5));
+    let geometry = disk.geometry();
+    let before = disk.data.clone();
+    let sector = [0xaa;SECTOR_SIZE];
+    {
+        let mut journal = Journal::begin(&mut disk, &geometry);
+        for target in 10..20 {
+            journal.write_sector(target, &sector);
+        }
+        assert_eq!(journal.commit(), Err(Error::NoSpace));
+        // the journal can be used again
+        journal.write_sector(10, &sector);
+        assert!(journal.commit().is_ok());
+    }
+    assert!(disk.data[10 * SECTOR_SIZE..11 * SECTOR_SIZE] == sector[..]);
+    assert!(disk.data[11 * SECTOR_SIZE..20 * SECTOR_SIZE] == before[11 * SECTOR_SIZE..20 * SECTOR_SIZE]);
+}
+
+#[test]
+fn garbage_journal_and_metadata_fields_are_ignored() {
+    let mut disk = MemDisk::new();
+    for byte in &mut disk.data[0x60..0x70] {
+        *byte = 0xff;
+    }
+    let geometry = disk.geometry();
+    assert!(!geometry.has_journal());
+    assert!(!geometry.has_meta());
+}
+
+#[test]
+fn zero_sectors_per_block_is_not_microfs() {
+    let mut disk = MemDisk::new();
+    disk.data[0x0d] = 0;
+    assert!(FsGeometry::from_superblock(&disk.data[0..SECTOR_SIZE]).is_none());
+}
\ No newline at end of file
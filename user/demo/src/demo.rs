@@ -12,7 +12,9 @@ pub extern fn main() {
     
     println!("\nIO demo :\n");
     println!("Executing hello app..");
-    exec("hello");
+    if let Err(err) = exec("hello") {
+        println!("hello: {}", err);
+    }
     println!("Waiting on keypressed..");
     while keypressed() == 0 {}
     getc();
@@ -26,22 +28,23 @@ pub extern fn main() {
     
     println!("File system demo :\n");
     println!("Opening file splash.txt..");
-    let fd = file_open("splash.txt");
-    if fd != -1 {
+    if let Ok(fd) = file_open("splash.txt") {
         println!("Reading file splash.txt..");
         let mut data = [0;MAX_STR_LEN];
-        file_read(fd as u32, &mut data[0], MAX_STR_LEN as u32);
-        println!("{}", bytes_to_str(&data));
+        file_read(fd, &mut data[0], MAX_STR_LEN as u32).ok();
+        println!("{}", bytes_to_str(&data).unwrap_or(""));
         println!("Closing file splash.txt..");
-        file_close(fd as u32);
+        file_close(fd).ok();
     }
     println!("Iterating all the files..", );
     let it = file_iterator();
     let mut bytes = [0;MAX_FILENAME_LENGTH];
-    while file_next(&bytes[0], &it) != -1 {
+    while file_next(&bytes[0], &it).is_ok() {
         {
-            let filename = bytes_to_str(&bytes);
-            println!("{} {}", filename, file_stat(filename).size);
+            let filename = bytes_to_str(&bytes).unwrap_or("?");
+            if let Ok(stat) = file_stat(filename) {
+                println!("{} {}", filename, stat.size);
+            }
         }
         bytes = [0;MAX_FILENAME_LENGTH];
     }
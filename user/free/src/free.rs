@@ -0,0 +1,23 @@
+#![no_std]
+
+extern crate ulibc;
+use ulibc::*;
+use io::*;
+use procfs::*;
+
+#[no_mangle]
+pub extern fn main() {
+    let mut data = [0;PROC_FILE_SIZE];
+    if !read_proc("/proc/meminfo", &mut data) {
+        println!("free: /proc/meminfo: {}", Error::NotFound);
+        return;
+    }
+    let meminfo = bytes_to_str(&data).unwrap_or("");
+    
+    let total = proc_number(meminfo, "MemTotal");
+    let free = proc_number(meminfo, "MemFree");
+    println!("{:>8} {:>10} {:>10} {:>10}", "kB", "total", "used", "free");
+    println!("{:>8} {:>10} {:>10} {:>10}", "Mem:", total, total - free, free);
+    println!("{:>8} {:>10} {:>10} {:>10}", "Heap:", proc_number(meminfo, "HeapTotal"),
+        proc_number(meminfo, "HeapUsed"), proc_number(meminfo, "HeapFree"));
+}
\ No newline at end of file
@@ -0,0 +1,9 @@
+#![no_std]
+
+extern crate ulibc;
+use ulibc::procfs::print_tasks;
+
+#[no_mangle]
+pub extern fn main() {
+    print_tasks();
+}
\ No newline at end of file
@@ -8,44 +8,75 @@ use core::str::FromStr;
 const MAX_CMD_LEN: usize = MAX_FILENAME_LENGTH;
 
 fn cat(filename: &str) {
-    let fd = file_open(filename);
-    if fd != -1 {
-        let mut data = [0;MAX_STR_LEN];
-        while file_read(fd as u32, &mut data[0], MAX_STR_LEN as u32) != 0 {
-            {
-                let content = bytes_to_str(&data);
-                if content == "\0" {
+    let fd = match file_open(filename) {
+        Ok(fd) => fd,
+        Err(err) => {
+            println!("cat: {}: {}", filename, err);
+            return;
+        }
+    };
+    let mut data = [0;MAX_STR_LEN];
+    while file_read(fd, &mut data[0], MAX_STR_LEN as u32).unwrap_or(0) != 0 {
+        {
+            match bytes_to_str(&data) {
+                Ok(content) => println!("{}", content),
+                Err(_) => {
                     println!("cat: {}: Not a text file", filename);
                     break;
-                } else {
-                    println!("{}", content);
                 }
             }
-            data = [0;MAX_STR_LEN];
         }
-        file_close(fd as u32);
-    } else {
-        println!("cat: {}: No such file or directory", filename);
+        data = [0;MAX_STR_LEN];
+    }
+    file_close(fd).ok();
+}
+
+fn trace(prog: &str) {
+    if let Err(err) = exec_trace(prog) {
+        println!("trace: {}: {}", prog, err);
+        return;
+    }
+    let fd = match file_open("/proc/trace") {
+        Ok(fd) => fd,
+        Err(err) => {
+            println!("trace: /proc/trace: {}", err);
+            return;
+        }
+    };
+    let mut data = [0;MAX_STR_LEN];
+    while file_read(fd, &mut data[0], MAX_STR_LEN as u32).unwrap_or(0) != 0 {
+        print!("{}", bytes_to_str(&data).unwrap_or(""));
+        data = [0;MAX_STR_LEN];
     }
+    file_close(fd).ok();
 }
 
 fn help() {
 	puts("\n");
-	puts("ls           : list files present in the file system\n");
+	puts("ls [-l]      : list files present in the file system\n");
 	puts("cat <file>   : dump the content of <file> to the screen\n");
     puts("clear        : clear the screen\n");
 	puts("<prog>       : execute the program <prog>.\n");
+	puts("trace <prog> : execute <prog> and print the system calls it made\n");
 	puts("sleep <ms>   : sleep the specified number of milliseconds\n");
 	puts("exit         : exit the shell\n");
 }
 
-fn ls() {
+fn ls(long: bool) {
     let it = file_iterator();
     let mut bytes = [0;MAX_FILENAME_LENGTH];
-    while file_next(&bytes[0], &it) != -1 {
+    while file_next(&bytes[0], &it).is_ok() {
         {
-            let filename = bytes_to_str(&bytes);
-            println!("{} {}", filename, file_stat(filename).size);
+            let filename = bytes_to_str(&bytes).unwrap_or("?");
+            match file_stat(filename) {
+                Ok(ref stat) if long => {
+                    let date = DateTime::from_timestamp(stat.mtime);
+                    println!("{} {:>3} {:>8} {}-{:02}-{:02} {:02}:{:02} {}", bytes_to_str(&stat.mode_str()).unwrap_or(""), stat.uid,
+                        stat.size, date.year, date.month, date.day, date.hour, date.minute, filename);
+                }
+                Ok(stat) => println!("{} {}", filename, stat.size),
+                Err(err) => println!("ls: {}: {}", filename, err),
+            }
         }
         bytes = [0;MAX_FILENAME_LENGTH];
     }
@@ -77,8 +108,7 @@ pub extern fn main() {
         print!("$ ");
         read_cmd(&mut cmd[0]);
         println!();
-        let mut s = String::new(bytes_to_str(&cmd));
-        let mut args = s.to_string().split_whitespace();
+        let mut args = bytes_to_str(&cmd).unwrap_or("").split_whitespace();
         match args.next() {
             Some(cmd) => {
                 let arg = match args.next() {
@@ -90,7 +120,8 @@ pub extern fn main() {
                     "clear" => clear(),
                     "exit"  => break,
                     "help"  => help(),
-                    "ls"    => ls(),
+                    "ls"    => ls(arg == "-l"),
+                    "trace" => trace(arg),
                     "sleep" => {
                         let ms = match u32::from_str(arg) {
                             Ok(num) => num,
@@ -102,7 +133,11 @@ pub extern fn main() {
                         println!("Sleeping for {}ms..", ms);
                         sleep(ms);
                     }
-                    _ => { exec(cmd); }
+                    _ => {
+                        if let Err(err) = exec(cmd) {
+                            println!("{}: {}", cmd, err);
+                        }
+                    }
                 }
             }
             _ => continue
@@ -1,10 +1,30 @@
 global syscall
 
+section .data
+; 1 if the cpu supports sysenter, 0 if it does not, -1 until it is checked
+sysenter_ok dd -1
+
 section .text                      ; start of the text (code) section
 align 4                            ; the code must be 4 byte aligned
 
 ; int syscall(uint32_t nb, uint32_t arg1, uint32_t arg2, uint32_t arg3, uint32_t arg4);
+; Uses sysenter when the cpu supports it, int 48 otherwise.
 syscall:
+    cmp     dword [sysenter_ok],0
+    jg      syscall_sysenter
+    je      syscall_int
+
+    ; the kernel sets the sysenter MSRs up whenever cpuid reports the SEP flag
+    push    ebx                    ; cpuid overwrites ebx
+    mov     eax,1
+    cpuid
+    pop     ebx
+    shr     edx,11
+    and     edx,1
+    mov     [sysenter_ok],edx
+    jmp     syscall
+
+syscall_int:
     ; parameters cannot be passed into the stack because the trap/interrupt gate
     ; performs a stack switch (from user stack to kernel stack). By the time we're
     ; in the syscall handler we're accessing the kernel stack (tss.ss/tss.esp).
@@ -12,7 +32,7 @@ syscall:
     mov     ebp,esp
 
 	; save all general registers since we modify them below
-	; eax is not saved as it's used to store the syscall's return value 
+	; eax is not saved as it's used to store the syscall's return value
     push    ebx
     push    ecx
     push    edx
@@ -25,8 +45,8 @@ syscall:
     mov     edx,[ebp+20]
     mov     esi,[ebp+24]
     int     48
-   
-	; restore all general registers 
+
+	; restore all general registers
     pop     edi
     pop     esi
     pop     edx
@@ -37,3 +57,36 @@ syscall:
     pop     ebp
     ret
 
+syscall_sysenter:
+    push    ebp
+    mov     ebp,esp
+
+    push    ebx
+    push    ecx
+    push    edx
+    push    esi
+    push    edi
+
+    ; sysexit returns to the address in edx with the stack pointer in ecx,
+    ; so the arguments go in eax (nb), ebx, edi, ebp and esi instead
+    mov     eax,[ebp+8]
+    mov     ebx,[ebp+12]
+    mov     edi,[ebp+16]
+    mov     esi,[ebp+24]
+    push    ebp
+    mov     ebp,[ebp+20]
+    mov     ecx,esp
+    mov     edx,.return
+    sysenter
+.return:
+    pop     ebp
+
+    pop     edi
+    pop     esi
+    pop     edx
+    pop     ecx
+    pop     ebx
+
+    mov     esp,ebp
+    pop     ebp
+    ret
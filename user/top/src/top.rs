@@ -0,0 +1,45 @@
+#![no_std]
+
+extern crate ulibc;
+use ulibc::*;
+use io::*;
+use procfs::*;
+
+// the display is refreshed every second until a key is pressed
+const REFRESH_MS: u32 = 1000;
+const POLL_MS: u32 = 50;
+
+fn display() {
+    let mut data = [0;PROC_FILE_SIZE];
+    read_proc("/proc/uptime", &mut data);
+    println!("top - up {} s, press a key to quit", bytes_to_str(&data).unwrap_or("").trim());
+
+    read_proc("/proc/meminfo", &mut data);
+    let meminfo = bytes_to_str(&data).unwrap_or("");
+    println!("Mem:  {:>8} kB total {:>8} kB free", proc_number(meminfo, "MemTotal"),
+        proc_number(meminfo, "MemFree"));
+    println!("Heap: {:>8} kB total {:>8} kB used {:>8} kB free", proc_number(meminfo, "HeapTotal"),
+        proc_number(meminfo, "HeapUsed"), proc_number(meminfo, "HeapFree"));
+    println!("Swap: {:>8} kB total {:>8} kB free", proc_number(meminfo, "SwapTotal"),
+        proc_number(meminfo, "SwapFree"));
+    println!();
+    print_tasks();
+}
+
+#[no_mangle]
+pub extern fn main() {
+    loop {
+        clear();
+        display();
+        let mut waited = 0;
+        while waited < REFRESH_MS {
+            if keypressed() != 0 {
+                getc();
+                clear();
+                return;
+            }
+            sleep(POLL_MS);
+            waited += POLL_MS;
+        }
+    }
+}
\ No newline at end of file
@@ -1,17 +1,17 @@
 #![macro_use]
 
-use core::fmt::{Error, Write, Arguments};
+use core::fmt::{self, Write, Arguments};
 pub use common::*;
 
 extern "C" {
-    pub fn syscall(nb: Syscall, arg1: u32, arg2: u32, arg3: u32, arg4: u32) -> i32;
+    pub fn syscall(nb: u32, arg1: u32, arg2: u32, arg3: u32, arg4: u32) -> i32;
 }
 
 pub struct Stdout {}
 
 impl Write for Stdout {
-    fn write_str(&mut self, s: &str) -> Result<(), Error> {
-        unsafe { syscall(Syscall::Puts, &String::new(s) as *const String as u32,  0, 0, 0); }
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        unsafe { syscall(Syscall::Puts as u32, s.as_ptr() as u32, s.len() as u32, 0, 0); }
         Ok(())
     }
 }
@@ -25,6 +25,12 @@ macro_rules! print {
     ($($arg:tt)*) => (write_fmt(format_args!($($arg)*)));
 }
 
+/// Formats the arguments into a heap allocated String
+#[macro_export]
+macro_rules! format {
+    ($($arg:tt)*) => (format(format_args!($($arg)*)));
+}
+
 #[macro_export]
 macro_rules! println {
     () => (print!("\n"));
@@ -39,116 +45,148 @@ pub fn clear() {
 
 pub fn puts(s: &str) {
     unsafe {
-        syscall(Syscall::Puts, String::new(s).as_ptr() as u32,  0, 0, 0);
+        syscall(Syscall::Puts as u32, s.as_ptr() as u32, s.len() as u32, 0, 0);
     }
 }
 
 pub fn putc(byte: u8) {
     unsafe {
-        syscall(Syscall::Putc, byte as u32,  0, 0, 0);
+        syscall(Syscall::Putc as u32, byte as u32,  0, 0, 0);
+    }
+}
+
+/// Runs the program s and returns when it ends
+pub fn exec(s: &str) -> Result<(), Error> {
+    unsafe {
+        code_to_result(syscall(Syscall::Exec as u32, s.as_ptr() as u32, s.len() as u32, 0, 0)).map(|_| ())
     }
 }
 
-pub fn exec(s: &str) -> i32 {
-    unsafe { 
-        syscall(Syscall::Exec, String::new(s).as_ptr() as u32,  0, 0, 0)
+/// Runs the program s like exec, logging its system calls to /proc/trace
+pub fn exec_trace(s: &str) -> Result<(), Error> {
+    unsafe {
+        code_to_result(syscall(Syscall::Exec as u32, s.as_ptr() as u32, s.len() as u32, EXEC_TRACE, 0)).map(|_| ())
     }
 }
 
 pub fn keypressed() -> i32 {
     unsafe {
-        syscall(Syscall::Keypressed, 0, 0, 0, 0)
+        syscall(Syscall::Keypressed as u32, 0, 0, 0, 0)
     }
 }
 
 pub fn getc() -> u8 {
     unsafe {
-        syscall(Syscall::Getc, 0, 0, 0, 0) as u8
+        syscall(Syscall::Getc as u32, 0, 0, 0, 0) as u8
     }
 }
 
-pub fn file_stat(s: &str) -> Stat {
+pub fn file_stat(s: &str) -> Result<Stat, Error> {
     let mut stat = Stat::null();
     unsafe {
-        syscall(Syscall::FileStat, String::new(s).as_ptr() as u32, stat.as_ptr() as u32, 0, 0);
+        code_to_result(syscall(Syscall::FileStat as u32, s.as_ptr() as u32, s.len() as u32, stat.as_ptr() as u32, 0))?;
+    }
+    Ok(stat)
+}
+
+/// Opens the file s, returns its file descriptor
+pub fn file_open(s: &str) -> Result<u32, Error> {
+    unsafe {
+        code_to_result(syscall(Syscall::FileOpen as u32, s.as_ptr() as u32, s.len() as u32, 0, 0))
+    }
+}
+
+/// Opens the shared memory object called name, it is created with size bytes
+/// if no task uses it. The object is accessed by mapping the returned fd with mmap.
+pub fn shm_open(name: &str, size: usize) -> Result<u32, Error> {
+    unsafe {
+        code_to_result(syscall(Syscall::ShmOpen as u32, name.as_ptr() as u32, name.len() as u32, size as u32, 0))
+    }
+}
+
+pub fn file_close(fd: u32) -> Result<(), Error> {
+    unsafe {
+        code_to_result(syscall(Syscall::FileClose as u32, fd, 0, 0, 0)).map(|_| ())
     }
-    return stat;
 }
 
-pub fn file_open(s: &str) -> i32 {
+/// Reads at most n bytes of the file, returns the number of bytes read
+pub fn file_read(fd: u32, buf: *mut u8, n: u32) -> Result<usize, Error> {
     unsafe {
-        syscall(Syscall::FileOpen, String::new(s).as_ptr() as u32, 0, 0, 0)
+        code_to_result(syscall(Syscall::FileRead as u32, fd, buf as u32, n, 0)).map(|cnt| cnt as usize)
     }
 }
 
-pub fn file_close(fd: u32) -> i32 {
+/// Writes n bytes to the file, returns the number of bytes written
+pub fn file_write(fd: u32, buf: *const u8, n: u32) -> Result<usize, Error> {
     unsafe {
-        syscall(Syscall::FileClose, fd, 0, 0, 0)
+        code_to_result(syscall(Syscall::FileWrite as u32, fd, buf as u32, n, 0)).map(|cnt| cnt as usize)
     }
 }
 
-pub fn file_read(fd: u32, buf: *mut u8, n: u32) -> i32 {
+pub fn ioctl(fd: u32, request: u32, arg: u32) -> Result<u32, Error> {
     unsafe {
-        syscall(Syscall::FileRead, fd, buf as u32, n, 0)
+        code_to_result(syscall(Syscall::FileIoctl as u32, fd, request, arg, 0))
     }
 }
 
-pub fn file_seek(fd: u32, offset: u32) -> i32 {
+pub fn file_seek(fd: u32, offset: u32) -> Result<(), Error> {
     unsafe {
-        syscall(Syscall::FileSeek, fd, offset, 0, 0)
+        code_to_result(syscall(Syscall::FileSeek as u32, fd, offset, 0, 0)).map(|_| ())
     }
 }
 
 pub fn file_iterator() -> FileIterator {
     let mut it = FileIterator::null();
     unsafe {
-        syscall(Syscall::FileIterator, it.as_ptr() as u32, 0, 0, 0);
+        syscall(Syscall::FileIterator as u32, it.as_ptr() as u32, 0, 0, 0);
     }
     return it;
 }
 
-pub fn file_next(bytes: *const u8, it: *const FileIterator) -> i32 {
+/// Copies the name of the next file into bytes, NotFound after the last one
+pub fn file_next(bytes: *const u8, it: *const FileIterator) -> Result<(), Error> {
     unsafe {
-        syscall(Syscall::FileNext, bytes as u32, it as u32, 0, 0)
+        code_to_result(syscall(Syscall::FileNext as u32, bytes as u32, it as u32, 0, 0)).map(|_| ())
     }
 }
 
 pub fn get_ticks() -> u32 {
     unsafe {
-        syscall(Syscall::GetTicks, 0, 0, 0, 0) as u32
+        syscall(Syscall::GetTicks as u32, 0, 0, 0, 0) as u32
     }
 }
 
 pub fn sleep(ms: u32) {
     unsafe {
-        syscall(Syscall::Sleep, ms, 0, 0, 0);
+        syscall(Syscall::Sleep as u32, ms, 0, 0, 0);
     }
 }
 
 pub fn set_cursor(x: u32, y: u32) {
     unsafe {
-        syscall(Syscall::SetCursor, x, y, 0, 0);
+        syscall(Syscall::SetCursor as u32, x, y, 0, 0);
     }
 }
 
 pub fn get_cursor(x: *const u32, y: *const u32) {
     unsafe {
-        syscall(Syscall::GetCursor, x as u32, y as u32, 0, 0);
+        syscall(Syscall::GetCursor as u32, x as u32, y as u32, 0, 0);
     }
 }
 
 pub fn cursor_disable(cd: bool) {
     unsafe {
         if cd {
-            syscall(Syscall::CursorDisable, 1, 0, 0, 0);
+            syscall(Syscall::CursorDisable as u32, 1, 0, 0, 0);
         } else {
-            syscall(Syscall::CursorDisable, 0, 0, 0, 0);
+            syscall(Syscall::CursorDisable as u32, 0, 0, 0, 0);
         }
     }
 }
 
 pub fn copy_scr(scr: *const FrameBuffer) {
     unsafe {
-        syscall(Syscall::CopyScr, scr as u32, 0, 0, 0);
+        syscall(Syscall::CopyScr as u32, scr as u32, 0, 0, 0);
     }
 }
\ No newline at end of file
@@ -1,13 +1,20 @@
-// extern crate alloc;
 use core::mem::size_of;
+use core::cmp::min;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
 use rlibc::{memset,memcpy};
 use io::*;
 
 const FRAME_SIZE: usize = 0x1000;
-const HEAP_END: u32 = 0xffffffff;
+// the heap grows with the program break up to the area of the mappings
+const HEAP_END: u32 = USER_MMAP_START;
 
 static mut HEAP_START: u32 = 0;
 static mut HEAP_SIZE: usize = 0;
+static mut HEAP_BREAK: u32 = 0;
+
+/// Allocator of the alloc crate collections, built on malloc and free
+pub struct UserAllocator;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C, align(16))]
@@ -28,24 +35,87 @@ macro_rules! align {
     }
 }
 
-fn heap_init() {
+/// Moves the program break to addr
+pub fn brk(addr: u32) -> Result<(), Error> {
+    unsafe { code_to_result(syscall(Syscall::Brk as u32, addr, 0, 0, 0)).map(|_| ()) }
+}
+
+/// Moves the program break by incr bytes. Returns the previous break.
+pub fn sbrk(incr: i32) -> Result<u32, Error> {
+    unsafe { code_to_result(syscall(Syscall::Sbrk as u32, incr as u32, 0, 0, 0)) }
+}
+
+/// Maps len bytes of the file fd from offset, or of zeroed memory with MAP_ANONYMOUS.
+/// Returns the address of the mapping.
+pub fn mmap(addr: u32, len: usize, prot: u32, flags: u32, fd: i32, offset: u32) -> Result<u32, Error> {
+    let args = MmapArgs { addr: addr, len: len, prot: prot, flags: flags, fd: fd, offset: offset };
+    unsafe { code_to_result(syscall(Syscall::Mmap as u32, &args as *const MmapArgs as u32, 0, 0, 0)) }
+}
+
+pub fn munmap(addr: u32, len: usize) -> Result<(), Error> {
+    unsafe { code_to_result(syscall(Syscall::Munmap as u32, addr, len as u32, 0, 0)).map(|_| ()) }
+}
+
+pub fn mprotect(addr: u32, len: usize, prot: u32) -> Result<(), Error> {
+    unsafe { code_to_result(syscall(Syscall::Mprotect as u32, addr, len as u32, prot, 0)).map(|_| ()) }
+}
+
+/// Writes the modified pages of the MAP_SHARED file mappings back to the disk
+pub fn msync(addr: u32, len: usize) -> Result<(), Error> {
+    unsafe { code_to_result(syscall(Syscall::Msync as u32, addr, len as u32, 0, 0)).map(|_| ()) }
+}
+
+fn heap_init() -> bool {
     unsafe {
         if HEAP_START == 0 {
-            HEAP_START = syscall(Syscall::AllocFrame, 0, 0, 0, 0) as u32;
+            let start = match sbrk(FRAME_SIZE as i32) {
+                Ok(start) => start,
+                Err(_) => return false
+            };
+            HEAP_START = start;
+            HEAP_BREAK = start + FRAME_SIZE as u32;
             HEAP_SIZE = (HEAP_END - HEAP_START) as usize;
-            memset(HEAP_START as *mut u8, 0, FRAME_SIZE);
-            memcpy(HEAP_START as *mut u8, Header::null(0, HEAP_SIZE).as_ptr(), size_of::<Header>());
+            memcpy(HEAP_START as *mut u8, Header::null(0, HEAP_SIZE - size_of::<Header>()).as_ptr(), size_of::<Header>());
+        }
+        return true;
+    }
+}
+
+// Moves the program break up so that the bytes below end are mapped
+fn heap_grow(end: u32) -> bool {
+    unsafe {
+        if end > HEAP_BREAK {
+            if brk(end).is_err() {
+                return false;
+            }
+            HEAP_BREAK = end;
+        }
+        return true;
+    }
+}
+
+// Gives the pages above end back to the kernel
+fn heap_shrink(end: u32) {
+    unsafe {
+        if HEAP_BREAK >= end + FRAME_SIZE as u32 && brk(end).is_ok() {
+            HEAP_BREAK = end;
         }
     }
 }
 
 pub fn malloc(size: usize) -> u32 {
-    heap_init();
+    if !heap_init() {
+        return 0;
+    }
     let aligned_size = align!(size);
     let mut addr = empty_block(aligned_size);
     let mut block = Header::from_ptr(addr as *mut u8);
     if block.size >= aligned_size && block.free {
         if block.next == 0 {
+            // the header of the new tail must be mapped too
+            if !heap_grow(addr + (aligned_size + 2 * size_of::<Header>()) as u32) {
+                return 0;
+            }
             block.insert_tail(addr, aligned_size);
         } else {
             block.insert(addr, aligned_size);
@@ -62,8 +132,6 @@ pub fn free(addr: u32) {
         let mut header_addr = addr - size_of::<Header>() as u32;
         let mut header = Header::from_ptr(header_addr as *const u8);
         if !header.free {
-            let start = header_addr;
-            let mut end = header.next;
             if header.previous != 0 {
                 let previous = Header::from_ptr(header.previous as *const u8);
                 if previous.free {
@@ -79,7 +147,6 @@ pub fn free(addr: u32) {
                     header.size += next.size + size_of::<Header>();
                 }
                 if header.next != 0 {
-                    end = header.next;
                     next = Header::from_ptr(header.next as *const u8);
                     if next.previous != header_addr {
                         next.previous = header_addr;
@@ -89,19 +156,32 @@ pub fn free(addr: u32) {
             }
             header.free = true;
             memcpy(header_addr as *mut u8, header.as_ptr(), size_of::<Header>());
-            // free unused page tables
-            let start_idx = start as usize / FRAME_SIZE;
-            let mut end_idx = end as usize / FRAME_SIZE;
+            // the tail block is free up to the end of the heap
             if header.next == 0 {
-                end_idx += 1;
-            }
-            for i in start_idx..end_idx {
-                syscall(Syscall::FreeFrame, (i * FRAME_SIZE) as u32, 0, 0, 0);
+                heap_shrink(header_addr + size_of::<Header>() as u32);
             }
         }
     }
 }
 
+/// Resizes the block allocated at addr, its content is kept up to the
+/// smallest of the two sizes. Returns the new address or 0 if the heap is full.
+pub fn realloc(addr: u32, size: usize) -> u32 {
+    if addr == 0 {
+        return malloc(size);
+    }
+    let header = Header::from_ptr((addr - size_of::<Header>() as u32) as *const u8);
+    if header.size >= align!(size) {
+        return addr;
+    }
+    let new_addr = malloc(size);
+    if new_addr != 0 {
+        unsafe { memcpy(new_addr as *mut u8, addr as *const u8, min(header.size, size)); }
+        free(addr);
+    }
+    return new_addr;
+}
+
 pub fn print_kmalloc_list() {
     let mut addr = unsafe { HEAP_START };
     while addr != 0 {
@@ -125,6 +205,43 @@ fn empty_block(size: usize) -> u32 {
     return addr;
 }
 
+unsafe impl GlobalAlloc for UserAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // malloc addresses are aligned on 16 bytes, for larger alignments
+        // the address returned by malloc is stored right before the object
+        if layout.align() <= size_of::<Header>() {
+            return malloc(layout.size()) as *mut u8;
+        }
+        let addr = malloc(layout.size() + layout.align());
+        if addr == 0 {
+            return null_mut();
+        }
+        let aligned_addr = (addr + layout.align() as u32) & !(layout.align() as u32 - 1);
+        *((aligned_addr - size_of::<u32>() as u32) as *mut u32) = addr;
+        aligned_addr as *mut u8
+    }
+    
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.align() <= size_of::<Header>() {
+            free(ptr as u32);
+        } else {
+            free(*((ptr as u32 - size_of::<u32>() as u32) as *const u32));
+        }
+    }
+    
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if layout.align() <= size_of::<Header>() {
+            return realloc(ptr as u32, new_size) as *mut u8;
+        }
+        let new_ptr = self.alloc(Layout::from_size_align_unchecked(new_size, layout.align()));
+        if !new_ptr.is_null() {
+            memcpy(new_ptr, ptr, min(layout.size(), new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
 impl Header {
     fn null(previous: u32, size: usize) -> Header {
         Header {
@@ -138,12 +255,6 @@ impl Header {
     fn insert(&mut self, addr: u32, size: usize) {
         unsafe {
             let total_size = size + size_of::<Header>();
-            if (addr as usize % FRAME_SIZE) + total_size > FRAME_SIZE {
-                for _i in 1..(total_size / FRAME_SIZE + 1) {
-                    let entry_addr = syscall(Syscall::AllocFrame, 0, 0, 0, 0) as u32;
-                    memset(entry_addr as *mut u8, 0, FRAME_SIZE);
-                }
-            }
             self.free = false;
             if size == self.size {
                 memcpy(addr as *mut u8, self.as_ptr(), size_of::<Header>());
@@ -170,13 +281,6 @@ impl Header {
             self.size = size;
             self.free = false;
             self.next = addr + total_size as u32;
-            // alloc new frames if need
-            if (addr as usize % FRAME_SIZE) + total_size > FRAME_SIZE {
-                for _i in 0..(total_size / FRAME_SIZE) {
-                    let entry_addr = syscall(Syscall::AllocFrame, 0, 0, 0, 0) as u32;
-                    memset(entry_addr as *mut u8, 0, FRAME_SIZE);
-                }
-            }
             let tail_size = (HEAP_END - self.next) as usize - size_of::<Header>();
             let mut tail = Header::null(addr, tail_size);
             memcpy(addr as *mut u8, self.as_ptr(), size_of::<Header>());
@@ -0,0 +1,49 @@
+//! Reading of the "key: value" files of /proc
+use core::str::FromStr;
+use io::*;
+use format;
+
+pub const PROC_FILE_SIZE: usize = 512;
+
+/// Value of the line key of the content of a /proc file, empty if there is none
+pub fn proc_field<'a>(data: &'a str, key: &str) -> &'a str {
+    for line in data.lines() {
+        let mut parts = line.splitn(2, ':');
+        if parts.next() == Some(key) {
+            return parts.next().unwrap_or("").trim();
+        }
+    }
+    return "";
+}
+
+/// Number of the line key, in kB for the sizes, 0 if there is none
+pub fn proc_number(data: &str, key: &str) -> usize {
+    usize::from_str(proc_field(data, key).trim_right_matches(" kB")).unwrap_or(0)
+}
+
+/// Reads the file path of /proc in data, false if it does not exist
+pub fn read_proc(path: &str, data: &mut [u8;PROC_FILE_SIZE]) -> bool {
+    *data = [0;PROC_FILE_SIZE];
+    match file_open(path) {
+        Ok(fd) => {
+            file_read(fd, &mut data[0], PROC_FILE_SIZE as u32).ok();
+            file_close(fd).ok();
+            true
+        }
+        Err(_) => false
+    }
+}
+
+/// Prints a line per task with its parent, state, frames, files and name
+pub fn print_tasks() {
+    let mut data = [0;PROC_FILE_SIZE];
+    println!("{:>4} {:>5} {:<8} {:>6} {:>4} {}", "PID", "PPID", "STATE", "FRAMES", "FDS", "NAME");
+    for pid in 0..MAX_TASKS {
+        if read_proc(&format!("{}{}/status", PROC_PATH, pid), &mut data) {
+            let status = bytes_to_str(&data).unwrap_or("");
+            println!("{:>4} {:>5} {:<8} {:>6} {:>4} {}", proc_field(status, "Pid"), proc_field(status, "PPid"),
+                proc_field(status, "State"), proc_field(status, "Frames"), proc_field(status, "Fds"),
+                proc_field(status, "Name"));
+        }
+    }
+}
\ No newline at end of file
@@ -1,5 +1,4 @@
-#![feature(lang_items)]
-// #![feature(alloc, global_allocator, allocator_api)]
+#![feature(lang_items, alloc, global_allocator, allocator_api)]
 #![no_std]
 
 extern crate common;
@@ -8,9 +7,21 @@ pub use common::*;
 extern crate rlibc;
 pub use rlibc::*;
 
+pub extern crate alloc;
+pub use alloc::vec::Vec;
+pub use alloc::string::{String, ToString};
+pub use alloc::boxed::Box;
+pub use alloc::fmt::format;
+
 pub mod io;
 pub mod curses;
 pub mod mem;
+pub mod procfs;
+
+use mem::UserAllocator;
+
+#[global_allocator]
+static ALLOCATOR: UserAllocator = UserAllocator;
 
 #[lang = "panic_fmt"]
 #[no_mangle]
@@ -18,6 +29,13 @@ pub extern "C" fn panic_fmt() -> ! {
     loop{}
 }
 
+#[lang = "oom"]
+#[no_mangle]
+pub extern fn rust_oom(layout: core::alloc::Layout) -> ! {
+    io::write_fmt(format_args!("out of memory: {} bytes requested\n", layout.size()));
+    loop {}
+}
+
 #[no_mangle]
 pub extern "C" fn __floatundisf() {
     loop {}